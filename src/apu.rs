@@ -0,0 +1,803 @@
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+#[derive(Debug)]
+struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn new() -> Self{
+        Envelope{
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    fn write(&mut self, val: u8){
+        self.initial_volume = val >> 4;
+        self.increase = val & 0x08 != 0;
+        self.period = val & 0x07;
+    }
+
+    fn read(&self) -> u8{
+        (self.initial_volume << 4) | ((self.increase as u8) << 3) | self.period
+    }
+
+    fn trigger(&mut self){
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    fn clock(&mut self){
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            }
+            else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Length {
+    counter: u16,
+    enabled: bool,
+    // 64 for most channels, 256 for the wave channel
+    max: u16,
+}
+
+impl Length {
+    fn new(max: u16) -> Self{
+        Length{
+            counter: 0,
+            enabled: false,
+            max,
+        }
+    }
+
+    fn load(&mut self, val: u8){
+        self.counter = self.max - val as u16;
+    }
+
+    // true when the counter just ran out and the channel should stop
+    fn clock(&mut self) -> bool{
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    // handles the length bits of an NRx4 write, frame_step is the step the frame sequencer runs next.
+    // returns false if the channel has to be turned off
+    fn write_control(&mut self, val: u8, frame_step: u8) -> bool{
+        let was_enabled = self.enabled;
+        self.enabled = val & 0x40 != 0;
+        let trigger = val & 0x80 != 0;
+        // turning length on during a step that doesn't clock length still clocks it once
+        let extra_clock = frame_step & 1 == 1;
+        let mut keep_playing = true;
+        if extra_clock && !was_enabled && self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            if self.counter == 0 && !trigger {
+                keep_playing = false;
+            }
+        }
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if extra_clock && self.enabled {
+                self.counter -= 1;
+            }
+        }
+        keep_playing
+    }
+}
+
+#[derive(Debug)]
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    enabled: bool,
+    shadow_freq: u16,
+    // clearing the negate bit after a subtraction was done kills the channel
+    negate_used: bool,
+}
+
+#[derive(Debug)]
+struct SquareChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    duty: u8,
+    duty_pos: u8,
+    length: Length,
+    envelope: Envelope,
+    frequency: u16,
+    freq_timer: u32,
+    // only channel 1 has a sweep unit
+    sweep: Option<Sweep>,
+}
+
+impl SquareChannel {
+    fn new(has_sweep: bool) -> Self{
+        SquareChannel{
+            enabled: false,
+            dac_enabled: false,
+            duty: 0,
+            duty_pos: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            frequency: 0,
+            freq_timer: 0,
+            sweep: if has_sweep {
+                Some(Sweep{ period: 0, negate: false, shift: 0, timer: 0, enabled: false, shadow_freq: 0, negate_used: false })
+            }
+            else {
+                None
+            },
+        }
+    }
+
+    fn read_reg(&self, reg: usize) -> u8{
+        match reg {
+            0 => match &self.sweep {
+                Some(sweep) => 0x80 | (sweep.period << 4) | ((sweep.negate as u8) << 3) | sweep.shift,
+                None => 0xFF,
+            },
+            1 => 0x3F | (self.duty << 6),
+            2 => self.envelope.read(),
+            3 => 0xFF,
+            4 => 0xBF | ((self.length.enabled as u8) << 6),
+            _ => 0xFF
+        }
+    }
+
+    fn write_reg(&mut self, reg: usize, val: u8, frame_step: u8){
+        match reg {
+            0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    sweep.period = (val >> 4) & 0x07;
+                    sweep.negate = val & 0x08 != 0;
+                    sweep.shift = val & 0x07;
+                    if !sweep.negate && sweep.negate_used {
+                        self.enabled = false;
+                    }
+                }
+            },
+            1 => {
+                self.duty = val >> 6;
+                self.length.load(val & 0x3F);
+            },
+            2 => {
+                self.envelope.write(val);
+                self.dac_enabled = val & 0xF8 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            },
+            3 => self.frequency = (self.frequency & 0x700) | val as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | (((val & 0x07) as u16) << 8);
+                if !self.length.write_control(val, frame_step) {
+                    self.enabled = false;
+                }
+                if val & 0x80 != 0 {
+                    self.trigger();
+                }
+            },
+            _ => ()
+        }
+    }
+
+    fn trigger(&mut self){
+        self.enabled = self.dac_enabled;
+        self.freq_timer = (2048 - self.frequency as u32) * 4;
+        self.envelope.trigger();
+        let frequency = self.frequency;
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.shadow_freq = frequency;
+            sweep.timer = if sweep.period == 0 { 8 } else { sweep.period };
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+            sweep.negate_used = false;
+        }
+        if self.sweep.as_ref().is_some_and(|sweep| sweep.shift != 0) {
+            self.sweep_calc();
+        }
+    }
+
+    // works out the next sweep frequency, anything past 2047 turns the channel off
+    fn sweep_calc(&mut self) -> u16{
+        let Some(sweep) = self.sweep.as_mut() else {
+            return self.frequency;
+        };
+        let delta = sweep.shadow_freq >> sweep.shift;
+        let new_freq = if sweep.negate {
+            sweep.negate_used = true;
+            sweep.shadow_freq - delta
+        }
+        else {
+            sweep.shadow_freq + delta
+        };
+        if new_freq > 2047 {
+            self.enabled = false;
+        }
+        new_freq
+    }
+
+    fn clock_sweep(&mut self){
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer != 0 {
+            return;
+        }
+        sweep.timer = if sweep.period == 0 { 8 } else { sweep.period };
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+        let new_freq = self.sweep_calc();
+        let shift = self.sweep.as_ref().map_or(0, |sweep| sweep.shift);
+        if new_freq <= 2047 && shift != 0 {
+            self.frequency = new_freq;
+            if let Some(sweep) = self.sweep.as_mut() {
+                sweep.shadow_freq = new_freq;
+            }
+            // the overflow check runs a second time with the new frequency
+            self.sweep_calc();
+        }
+    }
+
+    fn clock_length(&mut self){
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn tick(&mut self, tcycles: u32){
+        let mut remaining = tcycles;
+        while remaining > 0 {
+            if self.freq_timer == 0 {
+                self.freq_timer = (2048 - self.frequency as u32) * 4;
+            }
+            let step = remaining.min(self.freq_timer);
+            self.freq_timer -= step;
+            remaining -= step;
+            if self.freq_timer == 0 {
+                self.duty_pos = (self.duty_pos + 1) & 7;
+            }
+        }
+    }
+
+    // digital output, 0-15
+    fn output(&self) -> u8{
+        if !self.enabled || !self.dac_enabled {
+            return 0;
+        }
+        DUTY_PATTERNS[self.duty as usize][self.duty_pos as usize] * self.envelope.volume
+    }
+}
+
+#[derive(Debug)]
+struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    length: Length,
+    // 0 mutes, 1-3 shift the 4 bit samples right by 0-2
+    volume_code: u8,
+    frequency: u16,
+    freq_timer: u32,
+    position: u8,
+    // the wave ram byte the channel read last
+    sample_buffer: u8,
+    wave_ram: [u8; 16],
+}
+
+impl WaveChannel {
+    fn new() -> Self{
+        WaveChannel{
+            enabled: false,
+            dac_enabled: false,
+            length: Length::new(256),
+            volume_code: 0,
+            frequency: 0,
+            freq_timer: 0,
+            position: 0,
+            sample_buffer: 0,
+            wave_ram: [0x00; 16],
+        }
+    }
+
+    fn read_reg(&self, reg: usize) -> u8{
+        match reg {
+            0 => 0x7F | ((self.dac_enabled as u8) << 7),
+            1 => 0xFF,
+            2 => 0x9F | (self.volume_code << 5),
+            3 => 0xFF,
+            4 => 0xBF | ((self.length.enabled as u8) << 6),
+            _ => 0xFF
+        }
+    }
+
+    fn write_reg(&mut self, reg: usize, val: u8, frame_step: u8){
+        match reg {
+            0 => {
+                self.dac_enabled = val & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            },
+            1 => self.length.load(val),
+            2 => self.volume_code = (val >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x700) | val as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | (((val & 0x07) as u16) << 8);
+                if !self.length.write_control(val, frame_step) {
+                    self.enabled = false;
+                }
+                if val & 0x80 != 0 {
+                    self.enabled = self.dac_enabled;
+                    self.position = 0;
+                    // there's a short delay before the first sample gets fetched
+                    self.freq_timer = (2048 - self.frequency as u32) * 2 + 6;
+                }
+            },
+            _ => ()
+        }
+    }
+
+    // while the channel plays, the cpu only gets to see the byte the channel is reading
+    fn read_wave_ram(&self, index: usize) -> u8{
+        if self.enabled {
+            return self.sample_buffer;
+        }
+        self.wave_ram[index]
+    }
+
+    fn write_wave_ram(&mut self, index: usize, val: u8){
+        if self.enabled {
+            self.wave_ram[self.position as usize / 2] = val;
+            self.sample_buffer = val;
+            return;
+        }
+        self.wave_ram[index] = val;
+    }
+
+    fn clock_length(&mut self){
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn tick(&mut self, tcycles: u32){
+        if !self.enabled {
+            return;
+        }
+        let mut remaining = tcycles;
+        while remaining > 0 {
+            if self.freq_timer == 0 {
+                self.freq_timer = (2048 - self.frequency as u32) * 2;
+            }
+            let step = remaining.min(self.freq_timer);
+            self.freq_timer -= step;
+            remaining -= step;
+            if self.freq_timer == 0 {
+                self.position = (self.position + 1) & 31;
+                self.sample_buffer = self.wave_ram[self.position as usize / 2];
+            }
+        }
+    }
+
+    fn output(&self) -> u8{
+        if !self.enabled || !self.dac_enabled || self.volume_code == 0 {
+            return 0;
+        }
+        // high nibble first
+        let sample = if self.position & 1 == 0 { self.sample_buffer >> 4 } else { self.sample_buffer & 0x0F };
+        sample >> (self.volume_code - 1)
+    }
+}
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+#[derive(Debug)]
+struct NoiseChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    length: Length,
+    envelope: Envelope,
+    clock_shift: u8,
+    // 7 bit mode, the feedback goes into bit 6 as well as bit 14
+    short_mode: bool,
+    divisor_code: u8,
+    freq_timer: u32,
+    lfsr: u16,
+}
+
+impl NoiseChannel {
+    fn new() -> Self{
+        NoiseChannel{
+            enabled: false,
+            dac_enabled: false,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            clock_shift: 0,
+            short_mode: false,
+            divisor_code: 0,
+            freq_timer: 0,
+            lfsr: 0x7FFF,
+        }
+    }
+
+    fn period(&self) -> u32{
+        NOISE_DIVISORS[self.divisor_code as usize] << self.clock_shift
+    }
+
+    fn read_reg(&self, reg: usize) -> u8{
+        match reg {
+            0 => 0xFF,
+            1 => self.envelope.read(),
+            2 => (self.clock_shift << 4) | ((self.short_mode as u8) << 3) | self.divisor_code,
+            3 => 0xBF | ((self.length.enabled as u8) << 6),
+            _ => 0xFF
+        }
+    }
+
+    fn write_reg(&mut self, reg: usize, val: u8, frame_step: u8){
+        match reg {
+            0 => self.length.load(val & 0x3F),
+            1 => {
+                self.envelope.write(val);
+                self.dac_enabled = val & 0xF8 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            },
+            2 => {
+                self.clock_shift = val >> 4;
+                self.short_mode = val & 0x08 != 0;
+                self.divisor_code = val & 0x07;
+            },
+            3 => {
+                if !self.length.write_control(val, frame_step) {
+                    self.enabled = false;
+                }
+                if val & 0x80 != 0 {
+                    self.enabled = self.dac_enabled;
+                    self.lfsr = 0x7FFF;
+                    self.freq_timer = self.period();
+                    self.envelope.trigger();
+                }
+            },
+            _ => ()
+        }
+    }
+
+    fn clock_lfsr(&mut self){
+        let feedback = (self.lfsr & 1) ^ ((self.lfsr >> 1) & 1);
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+        if self.short_mode {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (feedback << 6);
+        }
+    }
+
+    fn clock_length(&mut self){
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn tick(&mut self, tcycles: u32){
+        // shifts of 14 and 15 never clock the lfsr
+        if !self.enabled || self.clock_shift >= 14 {
+            return;
+        }
+        let mut remaining = tcycles;
+        while remaining > 0 {
+            if self.freq_timer == 0 {
+                self.freq_timer = self.period();
+            }
+            let step = remaining.min(self.freq_timer);
+            self.freq_timer -= step;
+            remaining -= step;
+            if self.freq_timer == 0 {
+                self.clock_lfsr();
+            }
+        }
+    }
+
+    fn output(&self) -> u8{
+        if !self.enabled || !self.dac_enabled {
+            return 0;
+        }
+        (!self.lfsr & 1) as u8 * self.envelope.volume
+    }
+}
+
+#[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct APU {
+    ch1: SquareChannel,
+    ch2: SquareChannel,
+    ch3: WaveChannel,
+    ch4: NoiseChannel,
+    frame_step: u8,
+    frame_cycles: u32,
+    powered: bool,
+    nr50: u8,
+    nr51: u8,
+    sample_rate: u32,
+    // counts up by sample_rate every t-cycle, a sample is due each time it passes the cpu clock
+    sample_counter: u64,
+    // one high pass capacitor per side, takes the dc offset out like the real output stage
+    capacitor: [f32; 2],
+    // how much charge the capacitors keep per sample, depends on the sample rate
+    charge_factor: f32,
+    // interleaved left/right samples waiting for the frontend
+    samples: Vec<f32>,
+}
+
+impl APU {
+
+    pub const NR10_ADDR: usize = 0xFF10;
+    pub const NR14_ADDR: usize = 0xFF14;
+    pub const NR21_ADDR: usize = 0xFF16;
+    pub const NR24_ADDR: usize = 0xFF19;
+    pub const NR30_ADDR: usize = 0xFF1A;
+    pub const NR34_ADDR: usize = 0xFF1E;
+    pub const NR41_ADDR: usize = 0xFF20;
+    pub const NR44_ADDR: usize = 0xFF23;
+    pub const NR50_ADDR: usize = 0xFF24;
+    pub const NR51_ADDR: usize = 0xFF25;
+    pub const NR52_ADDR: usize = 0xFF26;
+    pub const WAVE_RAM_START: usize = 0xFF30;
+    pub const WAVE_RAM_END: usize = 0xFF3F;
+
+    // the frame sequencer runs at 512 Hz
+    const FRAME_SEQUENCER_TCYCLES: u32 = 8192;
+    const CPU_HZ: u64 = 4194304;
+    pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+    pub fn new() -> Self{
+        APU{
+            ch1: SquareChannel::new(true),
+            ch2: SquareChannel::new(false),
+            ch3: WaveChannel::new(),
+            ch4: NoiseChannel::new(),
+            frame_step: 0,
+            frame_cycles: 0,
+            powered: false,
+            nr50: 0,
+            nr51: 0,
+            sample_rate: Self::DEFAULT_SAMPLE_RATE,
+            sample_counter: 0,
+            capacitor: [0.0; 2],
+            charge_factor: Self::charge_factor(Self::DEFAULT_SAMPLE_RATE),
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, rate: u32){
+        self.sample_rate = rate;
+        self.sample_counter = 0;
+        self.charge_factor = Self::charge_factor(rate);
+    }
+
+    // 0.999958 per t-cycle, scaled to the sample rate
+    fn charge_factor(sample_rate: u32) -> f32{
+        0.999958f32.powf(Self::CPU_HZ as f32 / sample_rate as f32)
+    }
+
+    // about a second of stereo audio, past that nobody is draining the buffer so samples get dropped
+    fn max_buffered_samples(&self) -> usize{
+        2 * self.sample_rate as usize
+    }
+
+    pub fn sample_rate(&self) -> u32{
+        self.sample_rate
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32>{
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn is_apu_reg(addr: usize) -> bool{
+        // the unused holes (FF15, FF1F, FF27-FF2F) belong to the apu too and read back FF
+        (Self::NR10_ADDR..=Self::WAVE_RAM_END).contains(&addr)
+    }
+
+    pub fn read_reg(&self, addr: usize) -> u8{
+        match addr {
+            Self::NR10_ADDR..=Self::NR14_ADDR => self.ch1.read_reg(addr - Self::NR10_ADDR),
+            Self::NR21_ADDR..=Self::NR24_ADDR => self.ch2.read_reg(addr - Self::NR21_ADDR + 1),
+            Self::NR30_ADDR..=Self::NR34_ADDR => self.ch3.read_reg(addr - Self::NR30_ADDR),
+            Self::NR41_ADDR..=Self::NR44_ADDR => self.ch4.read_reg(addr - Self::NR41_ADDR),
+            Self::NR50_ADDR => self.nr50,
+            Self::NR51_ADDR => self.nr51,
+            Self::NR52_ADDR => {
+                0x70 | ((self.powered as u8) << 7) | (self.ch1.enabled as u8) | ((self.ch2.enabled as u8) << 1)
+                    | ((self.ch3.enabled as u8) << 2) | ((self.ch4.enabled as u8) << 3)
+            },
+            Self::WAVE_RAM_START..=Self::WAVE_RAM_END => self.ch3.read_wave_ram(addr - Self::WAVE_RAM_START),
+            _ => 0xFF
+        }
+    }
+
+    pub fn write_reg(&mut self, addr: usize, val: u8){
+        if addr == Self::NR52_ADDR {
+            self.write_nr52(val);
+            return;
+        }
+        if !self.powered {
+            // with the apu off only wave ram and (on dmg) the length counters can be written
+            match addr {
+                0xFF11 => self.ch1.length.load(val & 0x3F),
+                0xFF16 => self.ch2.length.load(val & 0x3F),
+                0xFF1B => self.ch3.length.load(val),
+                Self::NR41_ADDR => self.ch4.length.load(val & 0x3F),
+                Self::WAVE_RAM_START..=Self::WAVE_RAM_END => self.ch3.write_wave_ram(addr - Self::WAVE_RAM_START, val),
+                _ => ()
+            }
+            return;
+        }
+        match addr {
+            Self::NR10_ADDR..=Self::NR14_ADDR => self.ch1.write_reg(addr - Self::NR10_ADDR, val, self.frame_step),
+            Self::NR21_ADDR..=Self::NR24_ADDR => self.ch2.write_reg(addr - Self::NR21_ADDR + 1, val, self.frame_step),
+            Self::NR30_ADDR..=Self::NR34_ADDR => self.ch3.write_reg(addr - Self::NR30_ADDR, val, self.frame_step),
+            Self::NR41_ADDR..=Self::NR44_ADDR => self.ch4.write_reg(addr - Self::NR41_ADDR, val, self.frame_step),
+            Self::NR50_ADDR => self.nr50 = val,
+            Self::NR51_ADDR => self.nr51 = val,
+            Self::WAVE_RAM_START..=Self::WAVE_RAM_END => self.ch3.write_wave_ram(addr - Self::WAVE_RAM_START, val),
+            _ => ()
+        }
+    }
+
+    fn write_nr52(&mut self, val: u8){
+        let power = val & 0x80 != 0;
+        if self.powered && !power {
+            // powering off clears every register, wave ram and the dmg length counters survive
+            let lengths = [self.ch1.length.counter, self.ch2.length.counter, self.ch3.length.counter, self.ch4.length.counter];
+            let wave_ram = self.ch3.wave_ram;
+            self.ch1 = SquareChannel::new(true);
+            self.ch2 = SquareChannel::new(false);
+            self.ch3 = WaveChannel::new();
+            self.ch4 = NoiseChannel::new();
+            self.ch1.length.counter = lengths[0];
+            self.ch2.length.counter = lengths[1];
+            self.ch3.length.counter = lengths[2];
+            self.ch4.length.counter = lengths[3];
+            self.ch3.wave_ram = wave_ram;
+            self.nr50 = 0;
+            self.nr51 = 0;
+        }
+        else if !self.powered && power {
+            self.frame_step = 0;
+        }
+        self.powered = power;
+    }
+
+    pub fn tick(&mut self, mcycles: u8){
+        let tcycles = 4 * mcycles as u32;
+        self.ch1.tick(tcycles);
+        self.ch2.tick(tcycles);
+        self.ch3.tick(tcycles);
+        self.ch4.tick(tcycles);
+
+        self.frame_cycles += tcycles;
+        while self.frame_cycles >= Self::FRAME_SEQUENCER_TCYCLES {
+            self.frame_cycles -= Self::FRAME_SEQUENCER_TCYCLES;
+            if self.powered {
+                self.clock_frame_sequencer();
+            }
+        }
+
+        self.sample_counter += tcycles as u64 * self.sample_rate as u64;
+        while self.sample_counter >= Self::CPU_HZ {
+            self.sample_counter -= Self::CPU_HZ;
+            let (left, right) = self.mix();
+            if self.samples.len() < self.max_buffered_samples() {
+                self.samples.push(left);
+                self.samples.push(right);
+            }
+        }
+    }
+
+    // NR51 picks which channels go to which side, NR50 sets the volume of each side
+    fn mix(&mut self) -> (f32, f32){
+        if !self.powered {
+            return (0.0, 0.0);
+        }
+        let outputs = self.channel_outputs();
+        let dacs = [self.ch1.dac_enabled, self.ch2.dac_enabled, self.ch3.dac_enabled, self.ch4.dac_enabled];
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for i in 0..4 {
+            if !dacs[i] {
+                continue;
+            }
+            // the dacs turn 0-15 into -1.0 to 1.0
+            let analog = 1.0 - outputs[i] as f32 / 7.5;
+            if self.nr51 & (0x10 << i) != 0 {
+                left += analog;
+            }
+            if self.nr51 & (0x01 << i) != 0 {
+                right += analog;
+            }
+        }
+        let left_volume = (((self.nr50 >> 4) & 0x07) + 1) as f32 / 8.0;
+        let right_volume = ((self.nr50 & 0x07) + 1) as f32 / 8.0;
+        let any_dac = dacs.iter().any(|dac| *dac);
+        let left = self.high_pass(0, left / 4.0 * left_volume, any_dac);
+        let right = self.high_pass(1, right / 4.0 * right_volume, any_dac);
+        (left, right)
+    }
+
+    fn high_pass(&mut self, side: usize, input: f32, dacs_on: bool) -> f32{
+        if !dacs_on {
+            return 0.0;
+        }
+        let out = input - self.capacitor[side];
+        self.capacitor[side] = input - out * self.charge_factor;
+        out
+    }
+
+    // length on even steps, sweep on 2 and 6, envelope on 7
+    fn clock_frame_sequencer(&mut self){
+        if self.frame_step & 1 == 0 {
+            self.ch1.clock_length();
+            self.ch2.clock_length();
+            self.ch3.clock_length();
+            self.ch4.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.ch1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.ch1.envelope.clock();
+            self.ch2.envelope.clock();
+            self.ch4.envelope.clock();
+        }
+        self.frame_step = (self.frame_step + 1) & 7;
+    }
+
+    // digital output of each channel (0-15) for the mixer, silent channels give 0
+    pub fn channel_outputs(&self) -> [u8; 4]{
+        [self.ch1.output(), self.ch2.output(), self.ch3.output(), self.ch4.output()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unused_registers_read_ff(){
+        let mut apu = APU::new();
+        apu.write_reg(APU::NR52_ADDR, 0x80);
+        for addr in [0xFF15, 0xFF1F].into_iter().chain(0xFF27..=0xFF2F) {
+            assert!(APU::is_apu_reg(addr));
+            apu.write_reg(addr, 0x00);
+            assert_eq!(apu.read_reg(addr), 0xFF, "{:04X}", addr);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapperKind {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mmm01,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    PocketCamera,
+    Tama5,
+    HuC3,
+    HuC1,
+}
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    // the file is shorter than the header (or than the rom size the header claims)
+    Truncated { size: usize, expected: usize },
+    UnknownCartridgeType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    // a real mapper we know about but don't emulate yet
+    UnsupportedMapper(MapperKind),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self {
+            CartridgeError::Io(err) => write!(f, "couldn't read ROM file: {}", err),
+            CartridgeError::Truncated { size, expected } =>
+                write!(f, "ROM file is truncated: {} bytes, expected at least {}", size, expected),
+            CartridgeError::UnknownCartridgeType(code) => write!(f, "unknown cartridge type {:02X}", code),
+            CartridgeError::UnknownRomSize(code) => write!(f, "unknown ROM size code {:02X}", code),
+            CartridgeError::UnknownRamSize(code) => write!(f, "unknown RAM size code {:02X}", code),
+            CartridgeError::UnsupportedMapper(mapper) => write!(f, "{:?} cartridges aren't supported yet", mapper),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(err: io::Error) -> Self{
+        CartridgeError::Io(err)
+    }
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct Cartridge {
+    pub title: String,
+    pub cgb_flag: u8,
+    pub sgb_flag: u8,
+    pub cartridge_type: u8,
+    pub mapper: MapperKind,
+    pub has_ram: bool,
+    pub has_battery: bool,
+    pub has_rtc: bool,
+    pub has_rumble: bool,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    // two ascii chars when the old code is 0x33, otherwise the old code in hex
+    pub licensee: String,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    pub rom: Vec<u8>,
+}
+
+#[allow(dead_code)]
+impl Cartridge {
+
+    pub const HEADER_END: usize = 0x150;
+
+    const TITLE_ADDR: usize = 0x134;
+    const NEW_LICENSEE_ADDR: usize = 0x144;
+    const CGB_FLAG_ADDR: usize = 0x143;
+    const SGB_FLAG_ADDR: usize = 0x146;
+    const TYPE_ADDR: usize = 0x147;
+    const ROM_SIZE_ADDR: usize = 0x148;
+    const RAM_SIZE_ADDR: usize = 0x149;
+    const OLD_LICENSEE_ADDR: usize = 0x14B;
+    const VERSION_ADDR: usize = 0x14C;
+    const HEADER_CHECKSUM_ADDR: usize = 0x14D;
+    const GLOBAL_CHECKSUM_ADDR: usize = 0x14E;
+
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Self, CartridgeError>{
+        if rom.len() < Self::HEADER_END {
+            return Err(CartridgeError::Truncated { size: rom.len(), expected: Self::HEADER_END });
+        }
+
+        let cgb_flag = rom[Self::CGB_FLAG_ADDR];
+        // cgb carts give up the last title byte for the cgb flag
+        let title_len = if cgb_flag & 0x80 != 0 { 15 } else { 16 };
+        let title = rom[Self::TITLE_ADDR..Self::TITLE_ADDR + title_len].iter()
+            .take_while(|&&c| c != 0)
+            .filter(|c| c.is_ascii_graphic() || **c == b' ')
+            .map(|&c| c as char)
+            .collect::<String>();
+
+        let cartridge_type = rom[Self::TYPE_ADDR];
+        // (mapper, ram, battery, rtc, rumble)
+        let (mapper, has_ram, has_battery, has_rtc, has_rumble) = match cartridge_type {
+            0x00 => (MapperKind::RomOnly, false, false, false, false),
+            0x01 => (MapperKind::Mbc1, false, false, false, false),
+            0x02 => (MapperKind::Mbc1, true, false, false, false),
+            0x03 => (MapperKind::Mbc1, true, true, false, false),
+            0x05 => (MapperKind::Mbc2, false, false, false, false),
+            0x06 => (MapperKind::Mbc2, false, true, false, false),
+            0x08 => (MapperKind::RomOnly, true, false, false, false),
+            0x09 => (MapperKind::RomOnly, true, true, false, false),
+            0x0B => (MapperKind::Mmm01, false, false, false, false),
+            0x0C => (MapperKind::Mmm01, true, false, false, false),
+            0x0D => (MapperKind::Mmm01, true, true, false, false),
+            0x0F => (MapperKind::Mbc3, false, true, true, false),
+            0x10 => (MapperKind::Mbc3, true, true, true, false),
+            0x11 => (MapperKind::Mbc3, false, false, false, false),
+            0x12 => (MapperKind::Mbc3, true, false, false, false),
+            0x13 => (MapperKind::Mbc3, true, true, false, false),
+            0x19 => (MapperKind::Mbc5, false, false, false, false),
+            0x1A => (MapperKind::Mbc5, true, false, false, false),
+            0x1B => (MapperKind::Mbc5, true, true, false, false),
+            0x1C => (MapperKind::Mbc5, false, false, false, true),
+            0x1D => (MapperKind::Mbc5, true, false, false, true),
+            0x1E => (MapperKind::Mbc5, true, true, false, true),
+            0x20 => (MapperKind::Mbc6, true, true, false, false),
+            0x22 => (MapperKind::Mbc7, true, true, false, true),
+            0xFC => (MapperKind::PocketCamera, true, true, false, false),
+            0xFD => (MapperKind::Tama5, true, true, false, false),
+            0xFE => (MapperKind::HuC3, true, true, true, false),
+            0xFF => (MapperKind::HuC1, true, true, false, false),
+            code => return Err(CartridgeError::UnknownCartridgeType(code)),
+        };
+
+        let rom_size = match rom[Self::ROM_SIZE_ADDR] {
+            code @ 0x00..=0x08 => 0x8000 << code,
+            code => return Err(CartridgeError::UnknownRomSize(code)),
+        };
+        if rom.len() < rom_size {
+            return Err(CartridgeError::Truncated { size: rom.len(), expected: rom_size });
+        }
+
+        let ram_size = match rom[Self::RAM_SIZE_ADDR] {
+            0x00 => 0,
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            code => return Err(CartridgeError::UnknownRamSize(code)),
+        };
+
+        let old_licensee = rom[Self::OLD_LICENSEE_ADDR];
+        let licensee = if old_licensee == 0x33 {
+            rom[Self::NEW_LICENSEE_ADDR..Self::NEW_LICENSEE_ADDR + 2].iter().map(|&c| c as char).collect()
+        }
+        else {
+            format!("{:02X}", old_licensee)
+        };
+
+        Ok(Cartridge {
+            title,
+            cgb_flag,
+            sgb_flag: rom[Self::SGB_FLAG_ADDR],
+            cartridge_type,
+            mapper,
+            has_ram,
+            has_battery,
+            has_rtc,
+            has_rumble,
+            rom_size,
+            ram_size,
+            licensee,
+            version: rom[Self::VERSION_ADDR],
+            header_checksum: rom[Self::HEADER_CHECKSUM_ADDR],
+            global_checksum: ((rom[Self::GLOBAL_CHECKSUM_ADDR] as u16) << 8) | rom[Self::GLOBAL_CHECKSUM_ADDR + 1] as u16,
+            rom,
+        })
+    }
+
+    pub fn supports_cgb(&self) -> bool{
+        self.cgb_flag & 0x80 != 0
+    }
+
+    pub fn cgb_only(&self) -> bool{
+        self.cgb_flag == 0xC0
+    }
+
+    pub fn supports_sgb(&self) -> bool{
+        self.sgb_flag == 0x03
+    }
+
+    // the boot rom refuses to start the game if this one is wrong
+    pub fn header_checksum_valid(&self) -> bool{
+        let mut checksum: u8 = 0;
+        for byte in &self.rom[Self::TITLE_ADDR..Self::HEADER_CHECKSUM_ADDR] {
+            checksum = checksum.wrapping_sub(*byte).wrapping_sub(1);
+        }
+        checksum == self.header_checksum
+    }
+
+    // nothing on real hardware checks this one
+    pub fn global_checksum_valid(&self) -> bool{
+        let mut checksum: u16 = 0;
+        for (i, byte) in self.rom.iter().enumerate() {
+            if i != Self::GLOBAL_CHECKSUM_ADDR && i != Self::GLOBAL_CHECKSUM_ADDR + 1 {
+                checksum = checksum.wrapping_add(*byte as u16);
+            }
+        }
+        checksum == self.global_checksum
+    }
+}
@@ -2,8 +2,10 @@ use std::cell::RefCell;
 use std::rc::Weak;
 use crate::interrupt::InterruptHandlerThing;
 use crate::memory::Mem;
+use crate::model::Model;
 
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     reg_a: u8,
     reg_b: u8,
@@ -24,7 +26,11 @@ pub struct CPU {
     
 }
 
+#[allow(clippy::needless_return)]
 impl CPU{
+    // 4194304 Hz, 4 t-cycles to an m-cycle
+    pub const MCYCLES_PER_SECOND: u32 = 1 << 20;
+
     pub fn new(memm : Weak<RefCell<Mem>>,intrrpt: Weak<RefCell<InterruptHandlerThing>>) -> Self{
         CPU{
             reg_a: 0x01,
@@ -46,6 +52,31 @@ impl CPU{
 
     }
 
+    // registers as the model's boot rom leaves them at 0x100
+    pub fn set_post_boot_state(&mut self, model: Model, header_checksum: u8){
+        let [a, f, b, c, d, e, h, l] = model.cpu_registers(header_checksum);
+        self.reg_a = a;
+        self.reg_f = f;
+        self.reg_b = b;
+        self.reg_c = c;
+        self.reg_d = d;
+        self.reg_e = e;
+        self.reg_h = h;
+        self.reg_l = l;
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
+    }
+
+    // power on state, the boot rom sets everything up itself
+    pub fn start_boot_rom(&mut self){
+        self.set_af(0);
+        self.set_bc(0);
+        self.set_de(0);
+        self.set_hl(0);
+        self.sp = 0;
+        self.pc = 0;
+    }
+
     fn get_af(&self) -> u16{
         ((self.reg_a as u16) << 8)|(self.reg_f as u16)
     }
@@ -378,6 +409,8 @@ impl CPU{
         self.run_opcode(opcode)
     }
     
+    // LD r,r with the same register on both sides stays a plain self assignment
+    #[allow(dead_code, clippy::self_assignment)]
     pub fn run_opcode(&mut self,op:u8) -> u8{
         let interrupt_handl = self.interrupt_thing.upgrade().expect("interrupt handler reference dropped!");
         let memory = self.mem.upgrade().expect("memory manager reference dropped!");
@@ -5,7 +5,7 @@ pub struct InterruptHandlerThing {
     pub ie: u8,
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::needless_return, clippy::let_and_return)]
 impl InterruptHandlerThing {
     pub fn new() -> Self{
         InterruptHandlerThing{
@@ -0,0 +1,123 @@
+use std::cell::RefCell;
+use std::rc::Weak;
+
+use crate::interrupt::InterruptHandlerThing;
+
+// true means held down
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Buttons {
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+    pub right: bool,
+    pub left: bool,
+    pub up: bool,
+    pub down: bool,
+}
+
+#[derive(Debug)]
+pub struct Joypad {
+    // bits 4 (d-pad) and 5 (buttons) of P1, a 0 selects that row
+    select: u8,
+    buttons: Buttons,
+    interrupt_handler: Weak<RefCell<InterruptHandlerThing>>,
+}
+
+impl Joypad {
+
+    pub const P1_ADDR: usize = 0xFF00;
+
+    pub fn new(intrrpt: Weak<RefCell<InterruptHandlerThing>>) -> Self{
+        Joypad{
+            select: 0x30,
+            buttons: Buttons::default(),
+            interrupt_handler: intrrpt,
+        }
+    }
+
+    // low nibble of P1, active low like the real lines
+    fn lines(&self) -> u8{
+        let mut lines = 0x0F;
+        let pressed = |held: bool, bit: u8| if held { !(1 << bit) } else { 0xFF };
+        if self.select & 0x10 == 0 {
+            lines &= pressed(self.buttons.right, 0) & pressed(self.buttons.left, 1)
+                & pressed(self.buttons.up, 2) & pressed(self.buttons.down, 3);
+        }
+        if self.select & 0x20 == 0 {
+            lines &= pressed(self.buttons.a, 0) & pressed(self.buttons.b, 1)
+                & pressed(self.buttons.select, 2) & pressed(self.buttons.start, 3);
+        }
+        lines
+    }
+
+    pub fn read(&self) -> u8{
+        0xC0 | self.select | self.lines()
+    }
+
+    pub fn write(&mut self, val: u8){
+        let old_lines = self.lines();
+        self.select = val & 0x30;
+        self.check_interrupt(old_lines);
+    }
+
+    pub fn set_buttons(&mut self, buttons: Buttons){
+        let old_lines = self.lines();
+        self.buttons = buttons;
+        self.check_interrupt(old_lines);
+    }
+
+    // the interrupt fires when any of the 4 lines goes from high to low
+    fn check_interrupt(&self, old_lines: u8){
+        if old_lines & !self.lines() & 0x0F != 0 {
+            let interrupt_handl = self.interrupt_handler.upgrade().expect("Interrupt handler reference dropped!");
+            interrupt_handl.borrow_mut().req_joypad();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    const JOYPAD_IRQ: u8 = 0x10;
+
+    fn joypad() -> (Joypad, Rc<RefCell<InterruptHandlerThing>>){
+        let interrupt_handler = Rc::new(RefCell::new(InterruptHandlerThing::new()));
+        (Joypad::new(Rc::downgrade(&interrupt_handler)), interrupt_handler)
+    }
+
+    #[test]
+    fn rows_read_active_low(){
+        let (mut joypad, _interrupt_handler) = joypad();
+        joypad.set_buttons(Buttons { right: true, up: true, b: true, start: true, ..Buttons::default() });
+        joypad.write(0x20);
+        assert_eq!(joypad.read(), 0xE0 | 0x0A);
+        joypad.write(0x10);
+        assert_eq!(joypad.read(), 0xD0 | 0x05);
+        // both rows selected read ANDed together, none selected reads all high
+        joypad.write(0x00);
+        assert_eq!(joypad.read(), 0xC0);
+        joypad.write(0x30);
+        assert_eq!(joypad.read(), 0xFF);
+    }
+
+    #[test]
+    fn interrupt_on_high_to_low_only(){
+        let (mut joypad, interrupt_handler) = joypad();
+        joypad.write(0x20);
+        joypad.set_buttons(Buttons { down: true, ..Buttons::default() });
+        assert_eq!(interrupt_handler.borrow().if_ & JOYPAD_IRQ, JOYPAD_IRQ);
+
+        interrupt_handler.borrow_mut().if_ = 0;
+        joypad.set_buttons(Buttons::default());
+        assert_eq!(interrupt_handler.borrow().if_ & JOYPAD_IRQ, 0, "release");
+        // the d-pad row is selected, the buttons aren't
+        joypad.set_buttons(Buttons { a: true, ..Buttons::default() });
+        assert_eq!(interrupt_handler.borrow().if_ & JOYPAD_IRQ, 0, "deselected row");
+        // selecting the row with a held button pulls a line low too
+        joypad.write(0x10);
+        assert_eq!(interrupt_handler.borrow().if_ & JOYPAD_IRQ, JOYPAD_IRQ, "row select");
+    }
+}
@@ -0,0 +1,118 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::sastaboy::SastaBoy;
+use crate::serial::SerialDevice;
+
+// what each end of the cable currently shows the other one
+#[derive(Debug, Default)]
+struct LinkState {
+    // SB of a side sitting on the external clock, waiting to be clocked
+    waiting: [Option<u8>; 2],
+    // byte the clocking side shifted into a waiting side, picked up on its next tick
+    delivered: [Option<u8>; 2],
+}
+
+// one end of an in-process link cable
+#[derive(Debug)]
+pub struct LinkPort {
+    side: usize,
+    state: Rc<RefCell<LinkState>>,
+}
+
+impl SerialDevice for LinkPort {
+    fn exchange(&mut self, out: u8) -> u8{
+        let other = 1 - self.side;
+        let mut state = self.state.borrow_mut();
+        // the other side only shifts when it's set up for a transfer, otherwise we read the line floating high
+        match state.waiting[other].take() {
+            Some(incoming) => {
+                state.delivered[other] = Some(out);
+                incoming
+            },
+            None => 0xFF
+        }
+    }
+
+    fn tick(&mut self, _mcycles: u8, waiting: Option<u8>) -> Option<u8>{
+        let mut state = self.state.borrow_mut();
+        state.waiting[self.side] = waiting;
+        if waiting.is_none() {
+            state.delivered[self.side] = None;
+            return None;
+        }
+        let incoming = state.delivered[self.side].take();
+        if incoming.is_some() {
+            state.waiting[self.side] = None;
+        }
+        incoming
+    }
+}
+
+// both ends of a fresh cable
+pub fn link_cable() -> (LinkPort, LinkPort){
+    let state = Rc::new(RefCell::new(LinkState::default()));
+    (LinkPort { side: 0, state: state.clone() }, LinkPort { side: 1, state })
+}
+
+pub fn connect(first: &SastaBoy, second: &SastaBoy){
+    let (first_port, second_port) = link_cable();
+    first.set_serial_device(Box::new(first_port));
+    second.set_serial_device(Box::new(second_port));
+}
+
+// runs two linked instances, always stepping whichever one is behind so neither gets
+// more than an instruction ahead and transfers land at the right time on both sides
+pub fn run_linked(first: &SastaBoy, second: &SastaBoy){
+    let mut first_cycles: u64 = 0;
+    let mut second_cycles: u64 = 0;
+    while !first.stopped() && !second.stopped() {
+        if first_cycles <= second_cycles {
+            first_cycles += first.step() as u64;
+        }
+        else {
+            second_cycles += second.step() as u64;
+        }
+    }
+    first.finish();
+    second.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // puts val in SB, starts a transfer with sc, waits for it and pushes what came in,
+    // then idles a bit so the other side can finish before jumping to FFFF
+    fn transfer_rom(name: &str, val: u8, sc: u8) -> String{
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        let code = [
+            0x3E, val, 0xE0, 0x01,
+            0x3E, sc, 0xE0, 0x02,
+            0xF0, 0x02, 0xE6, 0x80, 0x20, 0xFA,
+            0xF0, 0x01, 0xF5,
+            0x06, 0x00, 0x05, 0x20, 0xFD,
+            0xC3, 0xFF, 0xFF,
+        ];
+        rom[0x150..0x150 + code.len()].copy_from_slice(&code);
+        let path = std::env::temp_dir().join(format!("sastaboy_link_{}_{}.gb", name, std::process::id()));
+        fs::write(&path, rom).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn linked_instances_swap_bytes(){
+        let master = SastaBoy::new();
+        let slave = SastaBoy::new();
+        for (sasta_boy, path) in [(&master, transfer_rom("master", 0x42, 0x81)), (&slave, transfer_rom("slave", 0x99, 0x80))] {
+            sasta_boy.load_rom(&path).unwrap();
+            let _ = fs::remove_file(&path);
+        }
+        connect(&master, &slave);
+        run_linked(&master, &slave);
+        assert_eq!(master.cpu.borrow_mut().pop_stack() >> 8, 0x99);
+        assert_eq!(slave.cpu.borrow_mut().pop_stack() >> 8, 0x42);
+    }
+}
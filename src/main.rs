@@ -1,16 +1,166 @@
+mod apu;
+mod cartridge;
 mod cpu;
 mod interrupt;
+mod joypad;
+mod link;
+mod mbc;
 mod memory;
+mod model;
 mod timer;
+mod ppu;
+mod png;
+mod printer;
 mod sastaboy;
-use log::{debug,info,LevelFilter};
-use simple_logging;
+mod serial;
+mod tcp_link;
+mod wav;
+use std::io;
+use log::LevelFilter;
+use crate::model::Model;
+use crate::ppu::RenderMode;
+use crate::printer::GameBoyPrinter;
 use crate::sastaboy::SastaBoy;
+use crate::serial::{BlarggCapture, SerialDevice};
+use crate::tcp_link::TcpLink;
+
+// what goes on the link port, picked with --listen/--connect/--printer
+fn serial_device(option: &str, arg: &str) -> io::Result<Box<dyn SerialDevice>>{
+    match option {
+        "--printer" => Ok(Box::new(GameBoyPrinter::new(arg))),
+        "--listen" => Ok(Box::new(TcpLink::listen(arg)?)),
+        _ => Ok(Box::new(TcpLink::connect(arg)?)),
+    }
+}
+
+// usage: SastaBoy [rom] [--model dmg0|dmg|mgb|sgb|sgb2|cgb|agb] [--boot-rom path] [--ppu scanline|fifo]
+//                [--listen addr | --connect addr | --printer out_dir | --link other_rom]
+//                [--wav path] [--sample-rate hz]
 fn main(){
     // components are their own thing now :D
     simple_logging::log_to_file("gameboy_cpu.log", LevelFilter::Debug).unwrap();
-    let sasta_boy = SastaBoy::new();
-    sasta_boy.load_rom("test_roms\\02-interrupts.gb");
+    let mut rom_path = String::from("test_roms\\02-interrupts.gb");
+    let mut model = Model::DMG;
+    let mut render_mode = RenderMode::Scanline;
+    let mut boot_rom_path: Option<String> = None;
+    let mut serial_option: Option<(String, String)> = None;
+    let mut link_rom_path: Option<String> = None;
+    let mut wav_path: Option<String> = None;
+    let mut sample_rate: Option<u32> = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" | "--connect" | "--printer" => {
+                let Some(value) = args.next() else {
+                    println!("{} needs a value", arg);
+                    return;
+                };
+                serial_option = Some((arg, value));
+            },
+            "--link" => {
+                let Some(path) = args.next() else {
+                    println!("--link needs the other ROM");
+                    return;
+                };
+                link_rom_path = Some(path);
+            },
+            "--wav" => {
+                let Some(path) = args.next() else {
+                    println!("--wav needs a path");
+                    return;
+                };
+                wav_path = Some(path);
+            },
+            "--sample-rate" => {
+                let Some(rate) = args.next().and_then(|rate| rate.parse::<u32>().ok()).filter(|rate| *rate > 0) else {
+                    println!("--sample-rate needs a rate in Hz");
+                    return;
+                };
+                sample_rate = Some(rate);
+            },
+            "--boot-rom" => {
+                let Some(path) = args.next() else {
+                    println!("--boot-rom needs a path");
+                    return;
+                };
+                boot_rom_path = Some(path);
+            },
+            "--ppu" => {
+                let Some(name) = args.next() else {
+                    println!("--ppu needs scanline or fifo");
+                    return;
+                };
+                let Some(mode) = RenderMode::from_name(&name) else {
+                    println!("unknown renderer {}", name);
+                    return;
+                };
+                render_mode = mode;
+            },
+            "--model" => {
+                let Some(name) = args.next() else {
+                    println!("--model needs a model name");
+                    return;
+                };
+                let Some(chosen) = Model::from_name(&name) else {
+                    println!("unknown model {}", name);
+                    return;
+                };
+                model = chosen;
+            },
+            _ => rom_path = arg,
+        }
+    }
+
+    if link_rom_path.is_some() && serial_option.is_some() {
+        println!("--link can't be used with --listen, --connect or --printer");
+        return;
+    }
+
+    let sasta_boy = SastaBoy::with_model(model);
+    println!("model: {:?}", sasta_boy.model());
+    sasta_boy.set_render_mode(render_mode);
+    if let Err(err) = sasta_boy.load_rom(&rom_path){
+        println!("{}", err);
+        return;
+    }
+    if let Some(path) = boot_rom_path {
+        if let Err(err) = sasta_boy.load_boot_rom(&path){
+            println!("couldn't load boot ROM {}: {}", path, err);
+            return;
+        }
+    }
+    if let Some(rate) = sample_rate {
+        sasta_boy.set_sample_rate(rate);
+    }
+    if let Some(path) = wav_path {
+        if let Err(err) = sasta_boy.record_audio(&path){
+            println!("couldn't create {}: {}", path, err);
+            return;
+        }
+    }
+    if let Some(path) = link_rom_path {
+        // a second instance in this process on the other end of the cable
+        let partner = SastaBoy::with_model(model);
+        partner.set_render_mode(render_mode);
+        if let Err(err) = partner.load_rom(&path){
+            println!("{}", err);
+            return;
+        }
+        link::connect(&sasta_boy, &partner);
+        link::run_linked(&sasta_boy, &partner);
+        return;
+    }
+    match serial_option {
+        Some((option, value)) => match serial_device(&option, &value) {
+            Ok(device) => sasta_boy.set_serial_device(device),
+            Err(err) => {
+                println!("couldn't set up {} {}: {}", option, value, err);
+                return;
+            }
+        },
+        // test roms report over serial, this prints it and stops the run when they're done
+        None => sasta_boy.set_serial_device(Box::new(BlarggCapture::new())),
+    }
     println!("Emulator: {:?}", sasta_boy);
     println!("weeee wooo");
     sasta_boy.run();
@@ -0,0 +1,868 @@
+use std::fmt::Debug;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cartridge::{Cartridge, CartridgeError, MapperKind};
+use crate::cpu::CPU;
+
+// the cartridge side of the bus: 0000-7FFF reads come from rom and writes there go to the
+// mapper registers, A000-BFFF is external ram
+pub trait Mbc: Debug {
+    fn read_rom(&self, addr: usize) -> u8;
+    fn write_rom(&mut self, addr: usize, val: u8);
+    fn read_ram(&self, addr: usize) -> u8;
+    fn write_ram(&mut self, addr: usize, val: u8);
+    // for mappers with something that runs on its own clock
+    fn tick(&mut self, _mcycles: u8){}
+    // state of the rumble motor on carts that have one
+    fn rumble(&self) -> bool{
+        false
+    }
+    fn ram(&self) -> &[u8];
+    fn ram_mut(&mut self) -> &mut [u8];
+    // battery saves use the raw layout other emulators use: a dump of the cartridge ram,
+    // followed by the rtc registers on mbc3 carts with a clock
+    fn save_data(&self) -> Vec<u8>{
+        self.ram().to_vec()
+    }
+    fn load_save_data(&mut self, data: &[u8]){
+        load_ram_dump(self.ram_mut(), data);
+    }
+    // save_data without anything that changes by itself (the rtc's timestamp), to tell if a save is needed
+    fn save_contents(&self) -> Vec<u8>{
+        self.save_data()
+    }
+}
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+pub const RAM_START: usize = 0xA000;
+
+// save files from other emulators can be a bit shorter or longer than our ram
+fn load_ram_dump(ram: &mut [u8], data: &[u8]){
+    let len = ram.len().min(data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+// bank numbers past the end of the rom wrap around like the unconnected address lines do
+fn rom_byte(rom: &[u8], bank: usize, addr: usize) -> u8{
+    let bank = bank % (rom.len() / ROM_BANK_SIZE).max(1);
+    rom.get(bank * ROM_BANK_SIZE + (addr & 0x3FFF)).copied().unwrap_or(0xFF)
+}
+
+// same for ram banks, None when the ram is disabled or there isn't any
+fn ram_index(ram: &[u8], enabled: bool, bank: usize, addr: usize) -> Option<usize>{
+    if !enabled || ram.is_empty() {
+        return None;
+    }
+    Some((bank * RAM_BANK_SIZE + (addr - RAM_START)) % ram.len())
+}
+
+pub fn new_mbc(cart: Cartridge) -> Result<Box<dyn Mbc>, CartridgeError>{
+    Ok(match cart.mapper {
+        MapperKind::RomOnly => Box::new(RomOnly::new(cart.rom, cart.ram_size)),
+        MapperKind::Mbc1 => Box::new(Mbc1::new(cart.rom, cart.ram_size)),
+        MapperKind::Mbc2 => Box::new(Mbc2::new(cart.rom)),
+        MapperKind::Mbc3 => Box::new(Mbc3::new(cart.rom, cart.ram_size, cart.has_rtc)),
+        MapperKind::Mbc5 => Box::new(Mbc5::new(cart.rom, cart.ram_size, cart.has_rumble)),
+        // running these without their banking would just crash somewhere in the game
+        other => return Err(CartridgeError::UnsupportedMapper(other)),
+    })
+}
+
+// 32 KiB of rom and optionally up to 8 KiB of ram, no registers at all
+#[derive(Debug)]
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self{
+        RomOnly{
+            rom,
+            ram: vec![0x00; ram_size.min(RAM_BANK_SIZE)],
+        }
+    }
+}
+
+impl Mbc for RomOnly {
+    fn read_rom(&self, addr: usize) -> u8{
+        self.rom.get(addr).copied().unwrap_or(0xFF)
+    }
+
+    fn write_rom(&mut self, _addr: usize, _val: u8){}
+
+    fn read_ram(&self, addr: usize) -> u8{
+        self.ram.get(addr - RAM_START).copied().unwrap_or(0xFF)
+    }
+
+    fn write_ram(&mut self, addr: usize, val: u8){
+        if let Some(byte) = self.ram.get_mut(addr - RAM_START) {
+            *byte = val;
+        }
+    }
+
+    fn ram(&self) -> &[u8]{
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8]{
+        &mut self.ram
+    }
+}
+
+#[derive(Debug)]
+pub struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    // 5 bit rom bank register at 2000-3FFF
+    bank1: u8,
+    // 2 bit register at 4000-5FFF, upper rom bank bits or the ram bank
+    bank2: u8,
+    // mode 1 lets bank2 switch the 0000-3FFF rom area and the ram bank too
+    mode: bool,
+    // MBC1M collections only wire 4 bits of bank1, bank2 picks the game
+    multicart: bool,
+}
+
+impl Mbc1 {
+    const LOGO_ADDR: usize = 0x104;
+    const LOGO_LEN: usize = 0x30;
+
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Self{
+        let multicart = Self::detect_multicart(&rom);
+        if multicart {
+            println!("MBC1M multicart detected");
+        }
+        Mbc1{
+            rom,
+            ram: vec![0x00; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart,
+        }
+    }
+
+    // multicarts are 1 MiB with a copy of the nintendo logo at the start of every 256 KiB game
+    fn detect_multicart(rom: &[u8]) -> bool{
+        if rom.len() != 0x100000 {
+            return false;
+        }
+        let logo = &rom[Self::LOGO_ADDR..Self::LOGO_ADDR + Self::LOGO_LEN];
+        let copies = (1..4).filter(|game| {
+            let start = game * 0x10 * ROM_BANK_SIZE + Self::LOGO_ADDR;
+            &rom[start..start + Self::LOGO_LEN] == logo
+        }).count();
+        copies >= 2
+    }
+
+    fn bank2_shift(&self) -> u8{
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn ram_index(&self, addr: usize) -> Option<usize>{
+        let bank = if self.mode { self.bank2 as usize } else { 0 };
+        ram_index(&self.ram, self.ram_enabled, bank, addr)
+    }
+}
+
+impl Mbc for Mbc1 {
+    fn read_rom(&self, addr: usize) -> u8{
+        let upper = (self.bank2 as usize) << self.bank2_shift();
+        if addr < ROM_BANK_SIZE {
+            let bank = if self.mode { upper } else { 0 };
+            return rom_byte(&self.rom, bank, addr);
+        }
+        let mask = if self.multicart { 0x0F } else { 0x1F };
+        rom_byte(&self.rom, upper | (self.bank1 & mask) as usize, addr)
+    }
+
+    fn write_rom(&mut self, addr: usize, val: u8){
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // the zero check looks at all 5 bits, so bank 0x20 can't be picked directly
+                self.bank1 = val & 0x1F;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            },
+            0x4000..=0x5FFF => self.bank2 = val & 0x03,
+            _ => self.mode = val & 0x01 != 0,
+        }
+    }
+
+    fn read_ram(&self, addr: usize) -> u8{
+        match self.ram_index(addr) {
+            Some(index) => self.ram[index],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: usize, val: u8){
+        if let Some(index) = self.ram_index(addr) {
+            self.ram[index] = val;
+        }
+    }
+
+    fn ram(&self) -> &[u8]{
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8]{
+        &mut self.ram
+    }
+}
+
+#[derive(Debug)]
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    // 512 half bytes built into the mapper, only the low nibble of each byte is used
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    const RAM_SIZE: usize = 0x200;
+
+    pub fn new(rom: Vec<u8>) -> Self{
+        Mbc2{
+            rom,
+            ram: vec![0x00; Self::RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn read_rom(&self, addr: usize) -> u8{
+        let bank = if addr < ROM_BANK_SIZE { 0 } else { self.rom_bank as usize };
+        rom_byte(&self.rom, bank, addr)
+    }
+
+    fn write_rom(&mut self, addr: usize, val: u8){
+        if addr >= ROM_BANK_SIZE {
+            return;
+        }
+        // address bit 8 picks the register
+        if addr & 0x100 == 0 {
+            self.ram_enabled = val & 0x0F == 0x0A;
+        }
+        else {
+            self.rom_bank = val & 0x0F;
+            if self.rom_bank == 0 {
+                self.rom_bank = 1;
+            }
+        }
+    }
+
+    // the 512 bytes repeat all over A000-BFFF
+    fn read_ram(&self, addr: usize) -> u8{
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        self.ram[(addr - RAM_START) & 0x1FF] | 0xF0
+    }
+
+    fn write_ram(&mut self, addr: usize, val: u8){
+        if self.ram_enabled {
+            self.ram[(addr - RAM_START) & 0x1FF] = val & 0x0F;
+        }
+    }
+
+    fn ram(&self) -> &[u8]{
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8]{
+        &mut self.ram
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rtc {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    // 9 bit day counter
+    pub days: u16,
+    pub halt: bool,
+    pub day_carry: bool,
+}
+
+impl Rtc {
+    // the counters are only 6/6/5 bits wide, out of range values written by the game
+    // count up to the wrap around without carrying into the next register
+    fn advance_second(&mut self){
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days += 1;
+        if self.days > 0x1FF {
+            self.days = 0;
+            self.day_carry = true;
+        }
+    }
+
+    fn in_range(&self) -> bool{
+        self.seconds < 60 && self.minutes < 60 && self.hours < 24
+    }
+
+    // for the time the emulator was closed, which can be years on an old save
+    fn advance(&mut self, mut seconds: u64){
+        // out of range values have to count up to their wrap around one second at a time first
+        while seconds > 0 && !self.in_range() {
+            self.advance_second();
+            seconds -= 1;
+        }
+        let total = self.seconds as u64 + self.minutes as u64 * 60 + self.hours as u64 * 3600
+            + self.days as u64 * 86400 + seconds;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        let days = total / 86400;
+        if days > 0x1FF {
+            self.day_carry = true;
+        }
+        self.days = (days % 0x200) as u16;
+    }
+
+    fn read(&self, reg: u8) -> u8{
+        match reg {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => (self.days & 0xFF) as u8,
+            0x0C => ((self.days >> 8) as u8 & 0x01) | ((self.halt as u8) << 6) | ((self.day_carry as u8) << 7),
+            _ => 0xFF
+        }
+    }
+
+    fn write(&mut self, reg: u8, val: u8){
+        match reg {
+            0x08 => self.seconds = val & 0x3F,
+            0x09 => self.minutes = val & 0x3F,
+            0x0A => self.hours = val & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | val as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | (((val & 0x01) as u16) << 8);
+                self.halt = val & 0x40 != 0;
+                self.day_carry = val & 0x80 != 0;
+            },
+            _ => ()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    // enables the rtc registers as well
+    ram_enabled: bool,
+    rom_bank: u8,
+    // 00-03 picks a ram bank, 08-0C maps an rtc register into A000-BFFF
+    ram_select: u8,
+    has_rtc: bool,
+    rtc: Rtc,
+    // what the game reads, copied from the live clock by writing 00 then 01 to 6000-7FFF
+    latched: Rtc,
+    last_latch_write: u8,
+    rtc_cycles: u32,
+}
+
+impl Mbc3 {
+    const RTC_SAVE_LEN: usize = 48;
+
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rtc: bool) -> Self{
+        Mbc3{
+            rom,
+            ram: vec![0x00; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            has_rtc,
+            rtc: Rtc::default(),
+            latched: Rtc::default(),
+            last_latch_write: 0xFF,
+            rtc_cycles: 0,
+        }
+    }
+
+    fn ram_index(&self, addr: usize) -> Option<usize>{
+        if self.ram_select > 0x03 {
+            return None;
+        }
+        ram_index(&self.ram, self.ram_enabled, self.ram_select as usize, addr)
+    }
+
+    // 5 live registers and 5 latched ones as 32 bit little endian words, then the unix time
+    // of the save as 64 bits (older emulators only wrote 32 bits of it)
+    fn rtc_save_data(&self) -> Vec<u8>{
+        let mut data = self.rtc_registers();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        data.extend(now.to_le_bytes());
+        data
+    }
+
+    fn rtc_registers(&self) -> Vec<u8>{
+        let mut data = Vec::with_capacity(Self::RTC_SAVE_LEN);
+        for rtc in [&self.rtc, &self.latched] {
+            for reg in 0x08..=0x0C {
+                data.extend((rtc.read(reg) as u32).to_le_bytes());
+            }
+        }
+        data
+    }
+
+    fn load_rtc_save_data(&mut self, data: &[u8]){
+        if data.len() < Self::RTC_SAVE_LEN - 4 {
+            println!("rtc data in the save file is too short, ignoring it");
+            return;
+        }
+        let word = |i: usize| u32::from_le_bytes([data[i * 4], data[i * 4 + 1], data[i * 4 + 2], data[i * 4 + 3]]);
+        for (i, reg) in (0x08..=0x0C).enumerate() {
+            self.rtc.write(reg, word(i) as u8);
+            self.latched.write(reg, word(i + 5) as u8);
+        }
+        let saved_at = if data.len() >= Self::RTC_SAVE_LEN {
+            u64::from_le_bytes(data[40..48].try_into().unwrap())
+        }
+        else {
+            word(10) as u64
+        };
+        // the clock kept running while the emulator was closed
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        if !self.rtc.halt {
+            self.rtc.advance(now.saturating_sub(saved_at));
+        }
+    }
+
+    fn rtc_selected(&self) -> bool{
+        self.ram_enabled && self.has_rtc && (0x08..=0x0C).contains(&self.ram_select)
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn read_rom(&self, addr: usize) -> u8{
+        let bank = if addr < ROM_BANK_SIZE { 0 } else { self.rom_bank as usize };
+        rom_byte(&self.rom, bank, addr)
+    }
+
+    fn write_rom(&mut self, addr: usize, val: u8){
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = val & 0x7F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            },
+            0x4000..=0x5FFF => self.ram_select = val,
+            _ => {
+                if self.last_latch_write == 0x00 && val == 0x01 {
+                    self.latched = self.rtc;
+                }
+                self.last_latch_write = val;
+            }
+        }
+    }
+
+    fn read_ram(&self, addr: usize) -> u8{
+        if self.rtc_selected() {
+            return self.latched.read(self.ram_select);
+        }
+        match self.ram_index(addr) {
+            Some(index) => self.ram[index],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: usize, val: u8){
+        if self.rtc_selected() {
+            // writes set the live clock, the latched copy too so the game reads back what it wrote
+            if self.ram_select == 0x08 {
+                self.rtc_cycles = 0;
+            }
+            self.rtc.write(self.ram_select, val);
+            self.latched.write(self.ram_select, val);
+            return;
+        }
+        if let Some(index) = self.ram_index(addr) {
+            self.ram[index] = val;
+        }
+    }
+
+    fn tick(&mut self, mcycles: u8){
+        if !self.has_rtc || self.rtc.halt {
+            return;
+        }
+        // the rtc has its own 32768 Hz crystal, counting emulated m-cycles keeps it in step with the game
+        self.rtc_cycles += mcycles as u32;
+        while self.rtc_cycles >= CPU::MCYCLES_PER_SECOND {
+            self.rtc_cycles -= CPU::MCYCLES_PER_SECOND;
+            self.rtc.advance_second();
+        }
+    }
+
+    fn ram(&self) -> &[u8]{
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8]{
+        &mut self.ram
+    }
+
+    fn save_data(&self) -> Vec<u8>{
+        let mut data = self.ram.clone();
+        if self.has_rtc {
+            data.extend(self.rtc_save_data());
+        }
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]){
+        load_ram_dump(&mut self.ram, data);
+        if self.has_rtc && data.len() > self.ram.len() {
+            self.load_rtc_save_data(&data[self.ram.len()..]);
+        }
+    }
+
+    fn save_contents(&self) -> Vec<u8>{
+        let mut data = self.ram.clone();
+        if self.has_rtc {
+            data.extend(self.rtc_registers());
+        }
+        data
+    }
+}
+
+#[derive(Debug)]
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    // 9 bits, and unlike the other mappers bank 0 can be mapped at 4000-7FFF
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    // rumble carts wire ram bank bit 3 to the motor
+    rumble_on: bool,
+}
+
+impl Mbc5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> Self{
+        Mbc5{
+            rom,
+            ram: vec![0x00; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble_on: false,
+        }
+    }
+
+    fn ram_index(&self, addr: usize) -> Option<usize>{
+        ram_index(&self.ram, self.ram_enabled, self.ram_bank as usize, addr)
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn read_rom(&self, addr: usize) -> u8{
+        let bank = if addr < ROM_BANK_SIZE { 0 } else { self.rom_bank as usize };
+        rom_byte(&self.rom, bank, addr)
+    }
+
+    fn write_rom(&mut self, addr: usize, val: u8){
+        match addr {
+            // mbc5 wants exactly 0A, not just the low nibble
+            0x0000..=0x1FFF => self.ram_enabled = val == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | val as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | (((val & 0x01) as u16) << 8),
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble_on = val & 0x08 != 0;
+                    self.ram_bank = val & 0x07;
+                }
+                else {
+                    self.ram_bank = val & 0x0F;
+                }
+            },
+            _ => ()
+        }
+    }
+
+    fn read_ram(&self, addr: usize) -> u8{
+        match self.ram_index(addr) {
+            Some(index) => self.ram[index],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, addr: usize, val: u8){
+        if let Some(index) = self.ram_index(addr) {
+            self.ram[index] = val;
+        }
+    }
+
+    fn rumble(&self) -> bool{
+        self.rumble_on
+    }
+
+    fn ram(&self) -> &[u8]{
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8]{
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cart(cartridge_type: u8) -> Cartridge{
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x147] = cartridge_type;
+        Cartridge::from_bytes(rom).unwrap()
+    }
+
+    #[test]
+    fn unsupported_mappers_are_an_error(){
+        assert!(new_mbc(cart(0x01)).is_ok());
+        for cartridge_type in [0x0B, 0x20, 0x22, 0xFC, 0xFD, 0xFE, 0xFF] {
+            let mapper = cart(cartridge_type).mapper;
+            assert!(matches!(new_mbc(cart(cartridge_type)), Err(CartridgeError::UnsupportedMapper(kind)) if kind == mapper));
+        }
+    }
+
+    // the first byte of every bank is its number, the second one the bits past 8
+    fn banked_rom(banks: usize) -> Vec<u8>{
+        let mut rom = vec![0x00; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+            rom[bank * ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+        }
+        rom
+    }
+
+    fn with_logo(rom: &mut [u8], bank: usize){
+        for i in 0..Mbc1::LOGO_LEN {
+            rom[bank * ROM_BANK_SIZE + Mbc1::LOGO_ADDR + i] = 0xCE ^ i as u8;
+        }
+    }
+
+    #[test]
+    fn mbc1_bank_0_writes_pick_the_next_bank(){
+        let mut mbc = Mbc1::new(banked_rom(128), 0);
+        assert!(!mbc.multicart);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 1);
+        for (upper, bank) in [(1, 0x21), (2, 0x41), (3, 0x61)] {
+            mbc.write_rom(0x4000, upper);
+            mbc.write_rom(0x2000, 0x00);
+            assert_eq!(mbc.read_rom(0x4000), bank);
+            // the zero check looks at the 5 bit value, not what the game wrote
+            mbc.write_rom(0x2000, 0x20);
+            assert_eq!(mbc.read_rom(0x4000), bank);
+        }
+        mbc.write_rom(0x2000, 0x1F);
+        assert_eq!(mbc.read_rom(0x4000), 0x7F);
+    }
+
+    #[test]
+    fn mbc1_mode_1_banks_0000_and_ram(){
+        let mut mbc = Mbc1::new(banked_rom(128), 0x8000);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x02);
+        mbc.write_ram(0xA000, 0x11);
+        // mode 0: bank 0 at 0000 and ram bank 0 whatever bank2 says
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        assert_eq!(mbc.ram[0], 0x11);
+
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x40);
+        assert_eq!(mbc.read_rom(0x4000), 0x41);
+        mbc.write_ram(0xA000, 0x22);
+        assert_eq!(mbc.ram[2 * RAM_BANK_SIZE], 0x22);
+        assert_eq!(mbc.read_ram(0xA000), 0x22);
+
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0x11);
+        // ram reads 0xFF once it's disabled
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn mbc1m_uses_4_bit_bank1(){
+        let mut rom = banked_rom(64);
+        for game in 0..4 {
+            with_logo(&mut rom, game * 0x10);
+        }
+        let mut mbc = Mbc1::new(rom, 0);
+        assert!(mbc.multicart);
+        // bank2 moves in steps of 16 banks and bit 4 of bank1 is ignored
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_rom(0x2000, 0x12);
+        assert_eq!(mbc.read_rom(0x4000), 0x12);
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mbc.read_rom(0x4000), 0x32);
+        // picking the game in mode 1 maps its first bank at 0000
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x30);
+        // and 0x10 still counts as non zero, so it maps bank 0 of the game at 4000
+        mbc.write_rom(0x2000, 0x10);
+        assert_eq!(mbc.read_rom(0x4000), 0x30);
+
+        // 1 MiB with the logo only once isn't a multicart
+        let mut rom = banked_rom(64);
+        with_logo(&mut rom, 0);
+        assert!(!Mbc1::new(rom, 0).multicart);
+    }
+
+    #[test]
+    fn mbc5_banks_9_bits_and_rumble(){
+        let mut mbc = Mbc5::new(banked_rom(512), 0x8000, true);
+        let bank = |mbc: &Mbc5| mbc.read_rom(0x4000) as u16 | (mbc.read_rom(0x4001) as u16) << 8;
+        assert_eq!(bank(&mbc), 1);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(bank(&mbc), 0);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(bank(&mbc), 0x100);
+        mbc.write_rom(0x2000, 0x23);
+        assert_eq!(bank(&mbc), 0x123);
+        mbc.write_rom(0x3000, 0x00);
+        assert_eq!(bank(&mbc), 0x23);
+        assert_eq!(mbc.read_rom(0x0000), 0);
+
+        // bit 3 of the ram bank drives the motor instead of picking a bank
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x0B);
+        assert!(mbc.rumble());
+        mbc.write_ram(0xA000, 0x33);
+        assert_eq!(mbc.ram[3 * RAM_BANK_SIZE], 0x33);
+        mbc.write_rom(0x4000, 0x03);
+        assert!(!mbc.rumble());
+        assert_eq!(mbc.read_ram(0xA000), 0x33);
+
+        let mut mbc = Mbc5::new(banked_rom(4), 0x20000, false);
+        mbc.write_rom(0x4000, 0x0B);
+        assert!(!mbc.rumble());
+    }
+
+    fn rtc_cart() -> Mbc3{
+        let mut mbc = Mbc3::new(vec![0x00; 0x8000], 0x2000, true);
+        mbc.write_rom(0x0000, 0x0A);
+        mbc.write_rom(0x4000, 0x08);
+        mbc
+    }
+
+    fn latch(mbc: &mut Mbc3){
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+    }
+
+    #[test]
+    fn rtc_second_is_2_20_mcycles(){
+        let mut mbc = rtc_cart();
+        for _ in 0..(CPU::MCYCLES_PER_SECOND / 4 - 1) {
+            mbc.tick(4);
+        }
+        mbc.tick(3);
+        latch(&mut mbc);
+        assert_eq!(mbc.read_ram(RAM_START), 0);
+
+        mbc.tick(1);
+        latch(&mut mbc);
+        assert_eq!(mbc.read_ram(RAM_START), 1);
+    }
+
+    #[test]
+    fn rtc_latches_on_00_then_01(){
+        let mut mbc = rtc_cart();
+        latch(&mut mbc);
+        for _ in 0..(CPU::MCYCLES_PER_SECOND / 4) {
+            mbc.tick(4);
+        }
+        // still showing the old latch
+        assert_eq!(mbc.read_ram(RAM_START), 0);
+        // 01 without a 00 before it doesn't latch
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(RAM_START), 0);
+        latch(&mut mbc);
+        assert_eq!(mbc.read_ram(RAM_START), 1);
+    }
+
+    fn rtc_save(rtc: Rtc, saved_at: u64) -> Vec<u8>{
+        let mut data = vec![0x00; 0x2000];
+        for _ in 0..2 {
+            for reg in 0x08..=0x0C {
+                data.extend((rtc.read(reg) as u32).to_le_bytes());
+            }
+        }
+        data.extend(saved_at.to_le_bytes());
+        data
+    }
+
+    fn unix_now() -> u64{
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    #[test]
+    fn rtc_catches_up_on_an_old_save(){
+        let mut mbc = rtc_cart();
+        let rtc = Rtc { seconds: 50, minutes: 59, hours: 23, days: 0x1FE, ..Rtc::default() };
+        // 1 day, 1 hour, 1 minute and 15 seconds ago
+        mbc.load_save_data(&rtc_save(rtc, unix_now() - (86400 + 3600 + 60 + 15)));
+        assert_eq!((mbc.rtc.minutes, mbc.rtc.hours, mbc.rtc.days), (1, 1, 0));
+        assert!((5..=6).contains(&mbc.rtc.seconds));
+        assert!(mbc.rtc.day_carry);
+    }
+
+    #[test]
+    fn rtc_catch_up_from_the_epoch_is_instant(){
+        let mut mbc = rtc_cart();
+        mbc.load_save_data(&rtc_save(Rtc::default(), 0));
+        let now = unix_now();
+        assert!(mbc.rtc.day_carry);
+        assert_eq!(mbc.rtc.days as u64, now / 86400 % 0x200);
+        assert!(mbc.rtc.in_range());
+    }
+
+    #[test]
+    fn rtc_catch_up_wraps_out_of_range_values_first(){
+        let mut rtc = Rtc { seconds: 62, ..Rtc::default() };
+        rtc.advance(3);
+        // 62, 63, 0 without a carry, then on to 1
+        assert_eq!((rtc.seconds, rtc.minutes), (1, 0));
+    }
+
+    #[test]
+    fn save_contents_leave_out_the_timestamp(){
+        let mbc = rtc_cart();
+        assert_eq!(mbc.save_data().len(), 0x2000 + Mbc3::RTC_SAVE_LEN);
+        assert_eq!(mbc.save_contents(), mbc.save_data()[..0x2000 + Mbc3::RTC_SAVE_LEN - 8]);
+    }
+}
@@ -1,29 +1,161 @@
 use std::rc::Weak;
 use std::cell::RefCell;
 use crate::timer::Timer;
+use crate::apu::APU;
 use crate::interrupt::InterruptHandlerThing;
+use crate::joypad::Joypad;
+use crate::mbc::{self, Mbc};
+use crate::ppu::PPU;
+use crate::serial::Serial;
 
 #[derive(Debug)]
 pub struct Mem{
     memory: [u8; 0x10000],
+    pub cartridge: Option<Box<dyn Mbc>>,
+    // mapped over the start of the cartridge until the game writes to FF50
+    boot_rom: Option<Vec<u8>>,
     pub timer: Weak<RefCell<Timer>>,
+    pub ppu: Weak<RefCell<PPU>>,
+    pub joypad: Weak<RefCell<Joypad>>,
+    pub apu: Weak<RefCell<APU>>,
+    pub serial: Weak<RefCell<Serial>>,
     interrupt_handler: Weak<RefCell<InterruptHandlerThing>>,
+    dma_reg: u8,
+    dma_active: bool,
+    dma_source: usize,
+    dma_index: usize,
+    // a write to FF46 takes a cycle to kick in, an already running transfer carries on until then
+    dma_pending: Option<(usize, u8)>,
 }
 
 impl Mem{
     pub fn new(tim:Weak<RefCell<Timer>>,intrrpt:Weak<RefCell<InterruptHandlerThing>>) -> Self{
         Mem{
             memory: [0x00; 0x10000],
+            cartridge: None,
+            boot_rom: None,
             timer:tim,
+            ppu: Weak::new(),
+            joypad: Weak::new(),
+            apu: Weak::new(),
+            serial: Weak::new(),
             interrupt_handler: intrrpt,
+            dma_reg: 0xFF,
+            dma_active: false,
+            dma_source: 0,
+            dma_index: 0,
+            dma_pending: None,
         }
         
     }
 
+    pub const ROM_END: usize = 0x7FFF;
+    pub const EXT_RAM_END: usize = 0xBFFF;
+    pub const DMA_ADDR: usize = 0xFF46;
+    pub const BOOT_ROM_SIZE: usize = 0x100;
+    pub const BOOT_OFF_ADDR: usize = 0xFF50;
+    const DMA_LENGTH: usize = 0xA0;
+
+    // what FF46 reads before the game starts a dma
+    pub fn set_dma_register(&mut self, val: u8){
+        self.dma_reg = val;
+    }
+
+    pub fn set_boot_rom(&mut self, rom: Vec<u8>){
+        self.boot_rom = Some(rom);
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool{
+        self.boot_rom.is_some()
+    }
+
+    pub fn tick(&mut self, mcycles: u8){
+        for _ in 0..mcycles {
+            self.dma_step();
+        }
+        if let Some(cart) = self.cartridge.as_mut() {
+            cart.tick(mcycles);
+        }
+    }
+
+    // one byte per m-cycle, 160 bytes into oam
+    fn dma_step(&mut self){
+        if self.dma_active {
+            let val = self.read_bus(self.dma_source + self.dma_index);
+            let ppu = self.ppu.upgrade().expect("PPU reference dropped!");
+            ppu.borrow_mut().write_oam(PPU::OAM_START + self.dma_index, val);
+            self.dma_index += 1;
+            if self.dma_index == Self::DMA_LENGTH {
+                self.dma_active = false;
+            }
+        }
+        if let Some((source, delay)) = self.dma_pending {
+            if delay == 0 {
+                self.dma_active = true;
+                self.dma_source = source;
+                self.dma_index = 0;
+                self.dma_pending = None;
+            }
+            else {
+                self.dma_pending = Some((source, delay - 1));
+            }
+        }
+    }
+
+    fn start_dma(&mut self, val: u8){
+        self.dma_reg = val;
+        let mut source = (val as usize) << 8;
+        // E000 and up is echo ram as far as the dma is concerned
+        if source >= 0xE000 {
+            source -= 0x2000;
+        }
+        self.dma_pending = Some((source, 1));
+    }
+
+    // while a dma runs the cpu can only reach hram (and the io registers, they're on their own bus)
+    fn dma_blocks(&self, addr: usize) -> bool{
+        self.dma_active && addr < 0xFF00
+    }
+
+    // vram is locked while the ppu draws (mode 3) and oam from the oam scan on (modes 2 and 3),
+    // real hardware just drops these so log them to make them easy to spot
+    fn ppu_blocks(&self, addr: usize) -> bool{
+        let ppu = self.ppu.upgrade().expect("PPU reference dropped!");
+        let ppu = ppu.borrow();
+        if (PPU::VRAM_START..=PPU::VRAM_END).contains(&addr) && !ppu.vram_accessible(){
+            log::warn!("VRAM access at {:04X} blocked in mode {}", addr, ppu.mode);
+            return true;
+        }
+        if (PPU::OAM_START..=PPU::OAM_END).contains(&addr) && !ppu.oam_accessible(){
+            log::warn!("OAM access at {:04X} blocked in mode {}", addr, ppu.mode);
+            return true;
+        }
+        false
+    }
+
     pub fn read(&self, addr: usize) -> u8{
+        if self.dma_blocks(addr) || self.ppu_blocks(addr){
+            return 0xFF;
+        }
+        self.read_bus(addr)
+    }
+
+    fn read_bus(&self, addr: usize) -> u8{
         let timer = self.timer.upgrade().expect("Timer reference dropped!");
         let interrupt_handl = self.interrupt_handler.upgrade().expect("Interrupt handler reference dropped!");
-        if addr == Timer::DIV_ADDR {
+        let ppu = self.ppu.upgrade().expect("PPU reference dropped!");
+        if addr < Self::BOOT_ROM_SIZE {
+            if let Some(boot_rom) = self.boot_rom.as_ref() {
+                return boot_rom[addr];
+            }
+        }
+        if addr <= Self::ROM_END {
+            return self.cartridge.as_ref().map_or(0xFF, |cart| cart.read_rom(addr));
+        }
+        else if (mbc::RAM_START..=Self::EXT_RAM_END).contains(&addr) {
+            return self.cartridge.as_ref().map_or(0xFF, |cart| cart.read_ram(addr));
+        }
+        else if addr == Timer::DIV_ADDR {
             return timer.borrow().get_div();
         }
         else if addr == Timer::TAC_ADDR {
@@ -41,8 +173,36 @@ impl Mem{
         else if addr == InterruptHandlerThing::IF_ADDR{
             return interrupt_handl.borrow().if_ | 0xE0;
         }
-        else if addr == 0xFF44{
-            return 0x90;
+        else if (PPU::VRAM_START..=PPU::VRAM_END).contains(&addr){
+            return ppu.borrow().read_vram(addr);
+        }
+        else if (PPU::OAM_START..=PPU::OAM_END).contains(&addr){
+            return ppu.borrow().read_oam(addr);
+        }
+        else if PPU::is_ppu_reg(addr){
+            return ppu.borrow().read_reg(addr);
+        }
+        else if addr == Self::DMA_ADDR{
+            return self.dma_reg;
+        }
+        else if addr == Joypad::P1_ADDR{
+            let joypad = self.joypad.upgrade().expect("Joypad reference dropped!");
+            return joypad.borrow().read();
+        }
+        else if APU::is_apu_reg(addr){
+            let apu = self.apu.upgrade().expect("APU reference dropped!");
+            return apu.borrow().read_reg(addr);
+        }
+        else if addr == Self::BOOT_OFF_ADDR{
+            return 0xFF;
+        }
+        else if addr == Serial::SB_ADDR{
+            let serial = self.serial.upgrade().expect("Serial reference dropped!");
+            return serial.borrow().read_sb();
+        }
+        else if addr == Serial::SC_ADDR{
+            let serial = self.serial.upgrade().expect("Serial reference dropped!");
+            return serial.borrow().read_sc();
         }
         self.memory[addr]
     }
@@ -50,8 +210,25 @@ impl Mem{
     pub fn write(&mut self, addr: usize, val: u8){
         let timer = self.timer.upgrade().expect("Timer reference dropped!");
         let interrupt_handl = self.interrupt_handler.upgrade().expect("Interrupt handler reference dropped!");
+        let ppu = self.ppu.upgrade().expect("PPU reference dropped!");
 
-        if addr == Timer::DIV_ADDR{
+        if self.dma_blocks(addr) || self.ppu_blocks(addr){
+            return;
+        }
+        if addr <= Self::ROM_END {
+            // rom can't be written, these go to the mapper registers
+            if let Some(cart) = self.cartridge.as_mut() {
+                cart.write_rom(addr, val);
+            }
+            return;
+        }
+        else if (mbc::RAM_START..=Self::EXT_RAM_END).contains(&addr) {
+            if let Some(cart) = self.cartridge.as_mut() {
+                cart.write_ram(addr, val);
+            }
+            return;
+        }
+        else if addr == Timer::DIV_ADDR{
             timer.borrow_mut().write_div(); // does the div increment obscure thing
             return;
         }
@@ -75,6 +252,49 @@ impl Mem{
             interrupt_handl.borrow_mut().if_ = (val & 0x1F) | 0xE0;
             return;
         }
+        else if (PPU::VRAM_START..=PPU::VRAM_END).contains(&addr){
+            ppu.borrow_mut().write_vram(addr, val);
+            return;
+        }
+        else if (PPU::OAM_START..=PPU::OAM_END).contains(&addr){
+            ppu.borrow_mut().write_oam(addr, val);
+            return;
+        }
+        else if PPU::is_ppu_reg(addr){
+            ppu.borrow_mut().write_reg(addr, val);
+            return;
+        }
+        else if addr == Self::DMA_ADDR{
+            self.start_dma(val);
+            return;
+        }
+        else if addr == Joypad::P1_ADDR{
+            let joypad = self.joypad.upgrade().expect("Joypad reference dropped!");
+            joypad.borrow_mut().write(val);
+            return;
+        }
+        else if APU::is_apu_reg(addr){
+            let apu = self.apu.upgrade().expect("APU reference dropped!");
+            apu.borrow_mut().write_reg(addr, val);
+            return;
+        }
+        else if addr == Self::BOOT_OFF_ADDR{
+            // the boot rom writes 1 here right before jumping to 0x100, there's no way back
+            if val != 0 {
+                self.boot_rom = None;
+            }
+            return;
+        }
+        else if addr == Serial::SB_ADDR{
+            let serial = self.serial.upgrade().expect("Serial reference dropped!");
+            serial.borrow_mut().write_sb(val);
+            return;
+        }
+        else if addr == Serial::SC_ADDR{
+            let serial = self.serial.upgrade().expect("Serial reference dropped!");
+            serial.borrow_mut().write_sc(val);
+            return;
+        }
         self.memory[addr] = val;
     }
 }
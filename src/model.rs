@@ -0,0 +1,112 @@
+use crate::apu::APU;
+use crate::interrupt::InterruptHandlerThing;
+use crate::ppu::PPU;
+use crate::serial::Serial;
+use crate::timer::Timer;
+
+// which console we pretend to be, games and test roms tell them apart by the state the boot rom leaves behind
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Model {
+    DMG0,
+    #[default]
+    DMG,
+    MGB,
+    SGB,
+    SGB2,
+    CGB,
+    AGB,
+}
+
+impl Model {
+
+    pub fn from_name(name: &str) -> Option<Self>{
+        match name.to_ascii_lowercase().as_str() {
+            "dmg0" => Some(Model::DMG0),
+            "dmg" => Some(Model::DMG),
+            "mgb" => Some(Model::MGB),
+            "sgb" => Some(Model::SGB),
+            "sgb2" => Some(Model::SGB2),
+            "cgb" => Some(Model::CGB),
+            "agb" => Some(Model::AGB),
+            _ => None
+        }
+    }
+
+    // A F B C D E H L at 0x100, values from pan docs. the dmg and mgb boot roms
+    // leave H and C clear when the header checksum is 0
+    pub fn cpu_registers(self, header_checksum: u8) -> [u8; 8]{
+        let dmg_flags = if header_checksum == 0 { 0x80 } else { 0xB0 };
+        match self {
+            Model::DMG0 => [0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03],
+            Model::DMG => [0x01, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            Model::MGB => [0xFF, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            Model::SGB => [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60],
+            Model::SGB2 => [0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60],
+            Model::CGB => [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D],
+            Model::AGB => [0x11, 0x00, 0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D],
+        }
+    }
+
+    // the full internal div counter, not just the upper byte in FF04. on the sgbs it depends
+    // on how long the snes takes with the header packets so there's no one right value,
+    // the dmg one stands in
+    pub fn div_counter(self) -> u16{
+        match self {
+            Model::DMG0 => 0x1830,
+            Model::DMG | Model::MGB | Model::SGB | Model::SGB2 => 0xABCC,
+            Model::CGB | Model::AGB => 0x1EA0,
+        }
+    }
+
+    // io registers the boot rom touches, in write order. NR52 goes first since the apu
+    // ignores everything else while it's off
+    pub fn io_registers(self) -> Vec<(usize, u8)>{
+        // the sgbs don't play the boot sound so channel 1 isn't left on
+        let nr14 = if self.plays_boot_sound() { 0xBF } else { 0x3F };
+        let nr52 = if self.plays_boot_sound() { 0xF1 } else { 0xF0 };
+        let sc = if self.is_cgb() { 0x7F } else { 0x7E };
+        vec![
+            (APU::NR52_ADDR, nr52),
+            (Serial::SC_ADDR, sc),
+            (APU::NR10_ADDR, 0x80),
+            (0xFF11, 0xBF),
+            (0xFF12, 0xF3),
+            (0xFF13, 0xFF),
+            (APU::NR14_ADDR, nr14),
+            (APU::NR21_ADDR, 0x3F),
+            (0xFF17, 0x00),
+            (0xFF18, 0xFF),
+            (APU::NR24_ADDR, 0xBF),
+            (APU::NR30_ADDR, 0x7F),
+            (0xFF1B, 0xFF),
+            (0xFF1C, 0x9F),
+            (0xFF1D, 0xFF),
+            (APU::NR34_ADDR, 0xBF),
+            (APU::NR41_ADDR, 0xFF),
+            (0xFF21, 0x00),
+            (0xFF22, 0x00),
+            (APU::NR44_ADDR, 0xBF),
+            (APU::NR50_ADDR, 0x77),
+            (APU::NR51_ADDR, 0xF3),
+            (Timer::TAC_ADDR, 0xF8),
+            (PPU::LCDC_ADDR, 0x91),
+            (PPU::STAT_ADDR, 0x85),
+            (PPU::BGP_ADDR, 0xFC),
+            (InterruptHandlerThing::IF_ADDR, 0xE1),
+        ]
+    }
+
+    // FF46 isn't in io_registers since writing it starts a transfer, the cgb boot rom leaves 00 in it
+    pub fn dma_register(self) -> u8{
+        if self.is_cgb() { 0x00 } else { 0xFF }
+    }
+
+    pub fn is_cgb(self) -> bool{
+        matches!(self, Model::CGB | Model::AGB)
+    }
+
+    fn plays_boot_sound(self) -> bool{
+        !matches!(self, Model::SGB | Model::SGB2)
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// just enough png for 8 bit grayscale, the image data goes in uncompressed deflate blocks
+pub fn write_grayscale(path: &Path, width: usize, height: usize, pixels: &[u8]) -> io::Result<()>{
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width).take(height) {
+        raw.push(0); // no filter
+        raw.extend_from_slice(row);
+    }
+
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[8, 0, 0, 0, 0]); // bit depth, grayscale, deflate, no filter, no interlace
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    fs::write(path, png)
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]){
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8>{
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        out.push(last as u8);
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32{
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32{
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums(){
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn stored_blocks_split_at_64k(){
+        let data = vec![0x42; 0x10000 + 10];
+        let zlib = zlib_stored(&data);
+        // first block not final, 0xFFFF bytes
+        assert_eq!(&zlib[2..7], &[0x00, 0xFF, 0xFF, 0x00, 0x00]);
+        let second = 2 + 5 + 0xFFFF;
+        assert_eq!(&zlib[second..second + 5], &[0x01, 11, 0x00, !11, 0xFF]);
+        assert_eq!(zlib.len(), 2 + 5 + 0xFFFF + 5 + 11 + 4);
+    }
+
+    #[test]
+    fn writes_a_grayscale_image(){
+        let path = std::env::temp_dir().join(format!("sastaboy_png_{}.png", std::process::id()));
+        write_grayscale(&path, 2, 2, &[0x00, 0x55, 0xAA, 0xFF]).unwrap();
+        let png = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[24..29], &[8, 0, 0, 0, 0]);
+        let ihdr_crc = u32::from_be_bytes(png[29..33].try_into().unwrap());
+        assert_eq!(ihdr_crc, crc32(&png[12..29]));
+        // filter byte then the pixels, row by row
+        assert_eq!(&png[33 + 8 + 2 + 5..][..6], &[0x00, 0x00, 0x55, 0x00, 0xAA, 0xFF]);
+        assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+    }
+}
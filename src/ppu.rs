@@ -0,0 +1,693 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Weak;
+
+use crate::interrupt::InterruptHandlerThing;
+use crate::memory::Mem;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    // whole line drawn at the start of mode 3, mode 3 is always 172 dots
+    Scanline,
+    // dot by dot fetcher and fifos, registers are sampled while the line is drawn
+    PixelFifo,
+}
+
+impl RenderMode {
+    pub fn from_name(name: &str) -> Option<Self>{
+        match name.to_ascii_lowercase().as_str() {
+            "scanline" => Some(RenderMode::Scanline),
+            "fifo" => Some(RenderMode::PixelFifo),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ObjPixel {
+    color: u8,
+    obp1: bool,
+    bg_priority: bool,
+}
+
+// state of the pixel fifo renderer for the line being drawn
+#[derive(Debug)]
+struct Fifo {
+    bg: VecDeque<u8>,
+    obj: VecDeque<ObjPixel>,
+    fetch_step: u8,
+    fetch_x: u8,
+    tile_id: u8,
+    tile_lo: u8,
+    tile_hi: u8,
+    fetching_window: bool,
+    window_drawn: bool,
+    // pixels thrown away instead of being shown, used for SCX fine scroll and WX < 7
+    discard: u8,
+    lx: u8,
+    next_sprite: usize,
+    sprite_dots: u8,
+    start_delay: u8,
+}
+
+impl Fifo {
+    fn new() -> Self{
+        Fifo{
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+            fetch_step: 0,
+            fetch_x: 0,
+            tile_id: 0,
+            tile_lo: 0,
+            tile_hi: 0,
+            fetching_window: false,
+            window_drawn: false,
+            discard: 0,
+            lx: 0,
+            next_sprite: 0,
+            sprite_dots: 0,
+            start_delay: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sprite {
+    y: u8,
+    x: u8,
+    tile: u8,
+    flags: u8,
+}
+
+#[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct PPU {
+    vram: [u8; 0x2000],
+    oam: [u8; 0xA0],
+    pub lcdc: u8,
+    pub stat: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub ly: u8,
+    pub lyc: u8,
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+    pub wy: u8,
+    pub wx: u8,
+    dots: u16,
+    pub mode: u8,
+    // OR of all the enabled STAT sources, the interrupt fires on its rising edge
+    stat_line: bool,
+    // the window keeps its own line counter, it only moves on lines where the window was drawn
+    window_line: u8,
+    wy_triggered: bool,
+    // sprites picked by the oam scan for the current line, sorted by drawing priority
+    line_sprites: Vec<Sprite>,
+    pub render_mode: RenderMode,
+    fifo: Fifo,
+    // how long mode 3 took on the last drawn line
+    pub last_mode3_dots: u16,
+    interrupt_handler: Weak<RefCell<InterruptHandlerThing>>,
+    // shades 0-3 (0 = white) after the palette is applied
+    pub framebuffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+}
+
+impl PPU {
+
+    pub const VRAM_START: usize = 0x8000;
+    pub const VRAM_END: usize = 0x9FFF;
+    pub const OAM_START: usize = 0xFE00;
+    pub const OAM_END: usize = 0xFE9F;
+
+    pub const LCDC_ADDR: usize = 0xFF40;
+    pub const STAT_ADDR: usize = 0xFF41;
+    pub const SCY_ADDR: usize = 0xFF42;
+    pub const SCX_ADDR: usize = 0xFF43;
+    pub const LY_ADDR: usize = 0xFF44;
+    pub const LYC_ADDR: usize = 0xFF45;
+    pub const BGP_ADDR: usize = 0xFF47;
+    pub const OBP0_ADDR: usize = 0xFF48;
+    pub const OBP1_ADDR: usize = 0xFF49;
+    pub const WY_ADDR: usize = 0xFF4A;
+    pub const WX_ADDR: usize = 0xFF4B;
+
+    pub const MODE_HBLANK: u8 = 0;
+    pub const MODE_VBLANK: u8 = 1;
+    pub const MODE_OAM_SCAN: u8 = 2;
+    pub const MODE_DRAWING: u8 = 3;
+
+    const DOTS_PER_LINE: u16 = 456;
+    const OAM_SCAN_DOTS: u16 = 80;
+    const DRAWING_DOTS: u16 = 172;
+    const LINES_PER_FRAME: u8 = 154;
+    const MAX_SPRITES_PER_LINE: usize = 10;
+    // the first tile fetch of every line is thrown away on hardware, this stall stands in for
+    // it so a line without scroll, window or sprites takes the usual 172 dots
+    const FIFO_START_DELAY: u8 = 6;
+
+    pub fn new(intrrpt: Weak<RefCell<InterruptHandlerThing>>) -> Self{
+        PPU{
+            vram: [0x00; 0x2000],
+            oam: [0x00; 0xA0],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            dots: 0,
+            mode: Self::MODE_HBLANK,
+            stat_line: false,
+            window_line: 0,
+            wy_triggered: false,
+            line_sprites: Vec::with_capacity(Self::MAX_SPRITES_PER_LINE),
+            render_mode: RenderMode::Scanline,
+            fifo: Fifo::new(),
+            last_mode3_dots: 0,
+            interrupt_handler: intrrpt,
+            framebuffer: [0x00; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn is_ppu_reg(addr: usize) -> bool{
+        (Self::LCDC_ADDR..=Self::WX_ADDR).contains(&addr) && addr != Mem::DMA_ADDR
+    }
+
+    pub fn read_vram(&self, addr: usize) -> u8{
+        self.vram[addr - Self::VRAM_START]
+    }
+
+    pub fn write_vram(&mut self, addr: usize, val: u8){
+        self.vram[addr - Self::VRAM_START] = val;
+    }
+
+    pub fn read_oam(&self, addr: usize) -> u8{
+        self.oam[addr - Self::OAM_START]
+    }
+
+    pub fn write_oam(&mut self, addr: usize, val: u8){
+        self.oam[addr - Self::OAM_START] = val;
+    }
+
+    pub fn read_reg(&self, addr: usize) -> u8{
+        match addr {
+            Self::LCDC_ADDR => self.lcdc,
+            Self::STAT_ADDR => self.stat | 0x80 | ((self.ly == self.lyc) as u8) << 2 | self.mode,
+            Self::SCY_ADDR => self.scy,
+            Self::SCX_ADDR => self.scx,
+            Self::LY_ADDR => self.ly,
+            Self::LYC_ADDR => self.lyc,
+            Self::BGP_ADDR => self.bgp,
+            Self::OBP0_ADDR => self.obp0,
+            Self::OBP1_ADDR => self.obp1,
+            Self::WY_ADDR => self.wy,
+            Self::WX_ADDR => self.wx,
+            _ => 0xFF
+        }
+    }
+
+    pub fn write_reg(&mut self, addr: usize, val: u8){
+        match addr {
+            Self::LCDC_ADDR => self.write_lcdc(val),
+            Self::STAT_ADDR => {
+                self.stat = val & 0x78;
+                self.update_stat_line();
+            },
+            Self::SCY_ADDR => self.scy = val,
+            Self::SCX_ADDR => self.scx = val,
+            Self::LY_ADDR => (), // read only
+            Self::LYC_ADDR => {
+                self.lyc = val;
+                self.update_stat_line();
+            },
+            Self::BGP_ADDR => self.bgp = val,
+            Self::OBP0_ADDR => self.obp0 = val,
+            Self::OBP1_ADDR => self.obp1 = val,
+            Self::WY_ADDR => self.wy = val,
+            Self::WX_ADDR => self.wx = val,
+            _ => ()
+        }
+    }
+
+    fn lcd_enabled(&self) -> bool{
+        self.lcdc & 0x80 != 0
+    }
+
+    pub fn vram_accessible(&self) -> bool{
+        !self.lcd_enabled() || self.mode != Self::MODE_DRAWING
+    }
+
+    pub fn oam_accessible(&self) -> bool{
+        !self.lcd_enabled() || (self.mode != Self::MODE_OAM_SCAN && self.mode != Self::MODE_DRAWING)
+    }
+
+    fn write_lcdc(&mut self, val: u8){
+        let was_enabled = self.lcd_enabled();
+        self.lcdc = val;
+        if was_enabled && !self.lcd_enabled(){
+            // turning the lcd off resets LY and parks the ppu in hblank
+            self.ly = 0;
+            self.dots = 0;
+            self.mode = Self::MODE_HBLANK;
+            self.stat_line = false;
+        }
+        else if !was_enabled && self.lcd_enabled(){
+            self.ly = 0;
+            self.dots = 0;
+            self.mode = Self::MODE_OAM_SCAN;
+            self.start_frame();
+            self.oam_scan();
+            self.update_stat_line();
+        }
+    }
+
+    fn start_frame(&mut self){
+        self.window_line = 0;
+        self.wy_triggered = self.ly == self.wy;
+    }
+
+    fn update_stat_line(&mut self){
+        if !self.lcd_enabled(){
+            return;
+        }
+        let line = (self.stat & 0x40 != 0 && self.ly == self.lyc)
+            || (self.stat & 0x20 != 0 && self.mode == Self::MODE_OAM_SCAN)
+            || (self.stat & 0x10 != 0 && self.mode == Self::MODE_VBLANK)
+            || (self.stat & 0x08 != 0 && self.mode == Self::MODE_HBLANK);
+        if line && !self.stat_line{
+            let interrupt_handl = self.interrupt_handler.upgrade().expect("Interrupt handler reference dropped!");
+            interrupt_handl.borrow_mut().req_lcd();
+        }
+        self.stat_line = line;
+    }
+
+    pub fn tick(&mut self, mcycles: u8){
+        if !self.lcd_enabled(){
+            return;
+        }
+        for _ in 0..4 * mcycles as u16 {
+            self.step_dot();
+        }
+    }
+
+    fn step_dot(&mut self){
+        self.dots += 1;
+        if self.dots == Self::DOTS_PER_LINE {
+            self.dots = 0;
+            self.ly = (self.ly + 1) % Self::LINES_PER_FRAME;
+            if self.ly as usize == SCREEN_HEIGHT {
+                self.mode = Self::MODE_VBLANK;
+                let interrupt_handl = self.interrupt_handler.upgrade().expect("Interrupt handler reference dropped!");
+                interrupt_handl.borrow_mut().req_vblank();
+            }
+            else if (self.ly as usize) < SCREEN_HEIGHT {
+                if self.ly == 0 {
+                    self.start_frame();
+                }
+                self.mode = Self::MODE_OAM_SCAN;
+                if self.ly == self.wy {
+                    self.wy_triggered = true;
+                }
+                self.oam_scan();
+            }
+        }
+        else if (self.ly as usize) < SCREEN_HEIGHT {
+            if self.dots == Self::OAM_SCAN_DOTS {
+                self.mode = Self::MODE_DRAWING;
+                match self.render_mode {
+                    RenderMode::Scanline => self.render_line(),
+                    RenderMode::PixelFifo => self.start_fifo_line(),
+                }
+            }
+            else if self.mode == Self::MODE_DRAWING {
+                let done = match self.render_mode {
+                    RenderMode::Scanline => self.dots >= Self::OAM_SCAN_DOTS + Self::DRAWING_DOTS,
+                    RenderMode::PixelFifo => {
+                        self.fifo_step();
+                        self.fifo.lx as usize == SCREEN_WIDTH
+                    }
+                };
+                if done {
+                    self.mode = Self::MODE_HBLANK;
+                    self.last_mode3_dots = self.dots - Self::OAM_SCAN_DOTS;
+                    if self.render_mode == RenderMode::PixelFifo && self.fifo.window_drawn {
+                        self.window_line += 1;
+                    }
+                }
+            }
+        }
+        self.update_stat_line();
+    }
+
+    fn sprite_height(&self) -> u8{
+        if self.lcdc & 0x04 != 0 { 16 } else { 8 }
+    }
+
+    // picks the first 10 sprites in oam order that overlap LY, then orders them the way dmg
+    // draws them: smaller X wins and oam order breaks ties
+    fn oam_scan(&mut self){
+        let height = self.sprite_height();
+        let line = self.ly as u16 + 16;
+        self.line_sprites.clear();
+        for entry in self.oam.chunks_exact(4) {
+            let y = entry[0] as u16;
+            if line >= y && line < y + height as u16 {
+                self.line_sprites.push(Sprite{ y: entry[0], x: entry[1], tile: entry[2], flags: entry[3] });
+                if self.line_sprites.len() == Self::MAX_SPRITES_PER_LINE {
+                    break;
+                }
+            }
+        }
+        // stable sort so equal X keeps the oam order
+        self.line_sprites.sort_by_key(|sprite| sprite.x);
+    }
+
+    // colour index of a sprite at screen column x, 0 when the sprite doesn't cover x
+    fn sprite_pixel(&self, sprite: &Sprite, x: usize) -> u8{
+        let height = self.sprite_height();
+        let left = sprite.x as usize;
+        let mut row = (self.ly + 16).wrapping_sub(sprite.y);
+        // LCDC bit 2 can change after the oam scan picked the sprite
+        if x + 8 < left || x >= left || row >= height {
+            return 0;
+        }
+        let mut col = (x + 8 - left) as u8;
+        if sprite.flags & 0x20 != 0 {
+            col = 7 - col;
+        }
+        if sprite.flags & 0x40 != 0 {
+            row = height - 1 - row;
+        }
+        // in 8x16 mode bit 0 of the tile index is ignored, the bottom half is the next tile
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+        self.tile_pixel(tile as usize * 16, row, col)
+    }
+
+    // tile ids are unsigned from 0x8000 when LCDC bit 4 is set, otherwise signed from 0x9000
+    fn tile_addr(&self, tile_id: u8) -> usize{
+        if self.lcdc & 0x10 != 0 {
+            tile_id as usize * 16
+        }
+        else {
+            (0x1000 + (tile_id as i8 as i32) * 16) as usize
+        }
+    }
+
+    // returns the 2 bit colour index of a pixel in a tile
+    fn tile_pixel(&self, tile_addr: usize, row: u8, col: u8) -> u8{
+        let lo = self.vram[tile_addr + 2 * row as usize];
+        let hi = self.vram[tile_addr + 2 * row as usize + 1];
+        let bit = 7 - col;
+        (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1)
+    }
+
+    // x and y are positions inside the 256x256 map
+    fn map_pixel(&self, map_base: usize, x: u8, y: u8) -> u8{
+        let tile_id = self.vram[map_base + (y as usize / 8) * 32 + (x as usize / 8)];
+        self.tile_pixel(self.tile_addr(tile_id), y % 8, x % 8)
+    }
+
+    fn apply_palette(palette: u8, color: u8) -> u8{
+        (palette >> (color * 2)) & 3
+    }
+
+    fn render_line(&mut self){
+        let line = self.ly as usize;
+        let bg_map: usize = if self.lcdc & 0x08 != 0 { 0x1C00 } else { 0x1800 };
+        let win_map: usize = if self.lcdc & 0x40 != 0 { 0x1C00 } else { 0x1800 };
+        // on dmg LCDC bit 0 turns off the window too
+        let window_visible = self.lcdc & 0x21 == 0x21 && self.wy_triggered && self.wx <= 166;
+        let mut window_drawn = false;
+        let mut bg_colors = [0u8; SCREEN_WIDTH];
+        for (x, bg_color) in bg_colors.iter_mut().enumerate() {
+            // bg disabled means a blank (colour 0) background on dmg
+            let color = if window_visible && x + 7 >= self.wx as usize {
+                window_drawn = true;
+                self.map_pixel(win_map, (x + 7 - self.wx as usize) as u8, self.window_line)
+            }
+            else if self.lcdc & 0x01 != 0 {
+                let px = (x as u8).wrapping_add(self.scx);
+                let py = self.ly.wrapping_add(self.scy);
+                self.map_pixel(bg_map, px, py)
+            }
+            else {
+                0
+            };
+            *bg_color = color;
+            self.framebuffer[line * SCREEN_WIDTH + x] = Self::apply_palette(self.bgp, color);
+        }
+        if window_drawn {
+            self.window_line += 1;
+        }
+
+        if self.lcdc & 0x02 == 0 {
+            return;
+        }
+        for (x, bg_color) in bg_colors.iter().enumerate() {
+            // the first opaque sprite in priority order owns the pixel, even if it then loses to the bg
+            let mut owner = None;
+            for sprite in self.line_sprites.iter() {
+                let color = self.sprite_pixel(sprite, x);
+                if color != 0 {
+                    owner = Some((sprite, color));
+                    break;
+                }
+            }
+            let Some((sprite, color)) = owner else {
+                continue;
+            };
+            if sprite.flags & 0x80 != 0 && *bg_color != 0 {
+                continue;
+            }
+            let palette = if sprite.flags & 0x10 != 0 { self.obp1 } else { self.obp0 };
+            self.framebuffer[line * SCREEN_WIDTH + x] = Self::apply_palette(palette, color);
+        }
+    }
+
+    fn start_fifo_line(&mut self){
+        self.fifo.bg.clear();
+        self.fifo.obj.clear();
+        self.fifo.fetch_step = 0;
+        self.fifo.fetch_x = 0;
+        self.fifo.fetching_window = false;
+        self.fifo.window_drawn = false;
+        self.fifo.discard = self.scx & 7;
+        self.fifo.lx = 0;
+        self.fifo.next_sprite = 0;
+        self.fifo.sprite_dots = 0;
+        self.fifo.start_delay = Self::FIFO_START_DELAY;
+    }
+
+    // one dot of mode 3 in pixel fifo mode
+    fn fifo_step(&mut self){
+        if self.fifo.start_delay > 0 {
+            self.fifo.start_delay -= 1;
+            return;
+        }
+
+        // the SCX pixels get thrown away before any sprite can match
+        if self.fifo.sprite_dots == 0 && self.fifo.discard == 0 && self.lcdc & 0x02 != 0
+            && self.fifo.next_sprite < self.line_sprites.len()
+            && self.line_sprites[self.fifo.next_sprite].x <= self.fifo.lx + 8 {
+            // the bg fetcher has to have pixels ready and get through reading its next tile before
+            // the sprite fetch can start, that's the 0-5 extra dots on top of the fetch itself
+            if self.fifo.bg.is_empty() || self.fifo.fetch_step < 4 {
+                self.tick_fetcher();
+                return;
+            }
+            self.fifo.sprite_dots = 6;
+        }
+        // sprite fetches stall everything else for 6 dots
+        if self.fifo.sprite_dots > 0 {
+            self.fifo.sprite_dots -= 1;
+            if self.fifo.sprite_dots == 0 {
+                self.merge_sprite(self.line_sprites[self.fifo.next_sprite]);
+                self.fifo.next_sprite += 1;
+            }
+            return;
+        }
+
+        if !self.fifo.fetching_window && self.lcdc & 0x21 == 0x21 && self.wy_triggered
+            && self.wx <= 166 && self.fifo.lx + 7 >= self.wx {
+            // window start throws away the bg pixels and restarts the fetcher on the window map
+            self.fifo.bg.clear();
+            self.fifo.fetch_step = 0;
+            self.fifo.fetch_x = 0;
+            self.fifo.fetching_window = true;
+            self.fifo.window_drawn = true;
+            if self.fifo.lx == 0 {
+                self.fifo.discard = 7u8.saturating_sub(self.wx);
+            }
+        }
+
+        self.tick_fetcher();
+        self.shift_pixel();
+    }
+
+    fn tick_fetcher(&mut self){
+        match self.fifo.fetch_step {
+            1 => {
+                let addr = if self.fifo.fetching_window {
+                    let map: usize = if self.lcdc & 0x40 != 0 { 0x1C00 } else { 0x1800 };
+                    map + (self.window_line as usize / 8) * 32 + self.fifo.fetch_x as usize
+                }
+                else {
+                    let map: usize = if self.lcdc & 0x08 != 0 { 0x1C00 } else { 0x1800 };
+                    let x = ((self.scx / 8) as usize + self.fifo.fetch_x as usize) & 31;
+                    let y = self.ly.wrapping_add(self.scy) as usize;
+                    map + (y / 8) * 32 + x
+                };
+                self.fifo.tile_id = self.vram[addr];
+            },
+            3 => self.fifo.tile_lo = self.vram[self.fetcher_row_addr()],
+            5 => self.fifo.tile_hi = self.vram[self.fetcher_row_addr() + 1],
+            6 => {
+                // dmg only pushes into an empty fifo
+                if !self.fifo.bg.is_empty() {
+                    return;
+                }
+                for bit in (0..8).rev() {
+                    let color = (((self.fifo.tile_hi >> bit) & 1) << 1) | ((self.fifo.tile_lo >> bit) & 1);
+                    self.fifo.bg.push_back(color);
+                }
+                self.fifo.fetch_x = (self.fifo.fetch_x + 1) & 31;
+                self.fifo.fetch_step = 0;
+                return;
+            },
+            _ => ()
+        }
+        self.fifo.fetch_step += 1;
+    }
+
+    fn fetcher_row_addr(&self) -> usize{
+        let row = if self.fifo.fetching_window {
+            self.window_line % 8
+        }
+        else {
+            self.ly.wrapping_add(self.scy) % 8
+        };
+        self.tile_addr(self.fifo.tile_id) + 2 * row as usize
+    }
+
+    fn merge_sprite(&mut self, sprite: Sprite){
+        let height = self.sprite_height();
+        let mut row = (self.ly + 16).wrapping_sub(sprite.y);
+        if row >= height {
+            return;
+        }
+        if sprite.flags & 0x40 != 0 {
+            row = height - 1 - row;
+        }
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+        // sprites hanging off the left edge lose their first columns
+        let skip = 8u8.saturating_sub(sprite.x);
+        while self.fifo.obj.len() < 8 {
+            self.fifo.obj.push_back(ObjPixel{ color: 0, obp1: false, bg_priority: false });
+        }
+        for i in skip..8 {
+            let col = if sprite.flags & 0x20 != 0 { 7 - i } else { i };
+            let color = self.tile_pixel(tile as usize * 16, row, col);
+            let slot = &mut self.fifo.obj[(i - skip) as usize];
+            // whatever is already in the fifo came from a sprite with higher priority
+            if slot.color == 0 {
+                *slot = ObjPixel{ color, obp1: sprite.flags & 0x10 != 0, bg_priority: sprite.flags & 0x80 != 0 };
+            }
+        }
+    }
+
+    fn shift_pixel(&mut self){
+        let Some(mut bg_color) = self.fifo.bg.pop_front() else {
+            return;
+        };
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+        let obj = self.fifo.obj.pop_front();
+        if self.lcdc & 0x01 == 0 {
+            bg_color = 0;
+        }
+        let mut shade = Self::apply_palette(self.bgp, bg_color);
+        if let Some(obj) = obj {
+            if obj.color != 0 && self.lcdc & 0x02 != 0 && !(obj.bg_priority && bg_color != 0) {
+                let palette = if obj.obp1 { self.obp1 } else { self.obp0 };
+                shade = Self::apply_palette(palette, obj.color);
+            }
+        }
+        self.framebuffer[self.ly as usize * SCREEN_WIDTH + self.fifo.lx as usize] = shade;
+        self.fifo.lx += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    // mode 3 length of the first line with the lcd just turned on, setup runs before that
+    fn mode3_dots(render_mode: RenderMode, lcdc: u8, setup: impl Fn(&mut PPU)) -> (u16, u16){
+        let interrupt_handler = Rc::new(RefCell::new(InterruptHandlerThing::new()));
+        let mut ppu = PPU::new(Rc::downgrade(&interrupt_handler));
+        ppu.render_mode = render_mode;
+        setup(&mut ppu);
+        ppu.write_reg(PPU::LCDC_ADDR, lcdc);
+        let mut stat_mode3_dots = 0;
+        for _ in 0..PPU::DOTS_PER_LINE {
+            ppu.step_dot();
+            if ppu.mode == PPU::MODE_DRAWING {
+                stat_mode3_dots += 1;
+            }
+        }
+        (ppu.last_mode3_dots, stat_mode3_dots)
+    }
+
+    #[test]
+    fn plain_line_is_172_dots_in_both_renderers(){
+        let (scanline, scanline_stat) = mode3_dots(RenderMode::Scanline, 0x91, |_| ());
+        let (fifo, fifo_stat) = mode3_dots(RenderMode::PixelFifo, 0x91, |_| ());
+        assert_eq!(scanline, 172);
+        assert_eq!(fifo, 172);
+        assert_eq!(scanline_stat, 172);
+        assert_eq!(fifo_stat, 172);
+    }
+
+    fn with_sprites(ppu: &mut PPU, xs: &[u8]){
+        for (i, x) in xs.iter().enumerate() {
+            ppu.write_oam(PPU::OAM_START + 4 * i, 16);
+            ppu.write_oam(PPU::OAM_START + 4 * i + 1, *x);
+        }
+    }
+
+    #[test]
+    fn fifo_mode3_gets_longer(){
+        let fifo = |lcdc, setup: &dyn Fn(&mut PPU)| mode3_dots(RenderMode::PixelFifo, lcdc, setup).0;
+        // fine scroll throws away SCX & 7 pixels at the start of the line
+        for scx in 0..16u8 {
+            assert_eq!(fifo(0x91, &|ppu| ppu.scx = scx), 172 + (scx & 7) as u16);
+        }
+        // the fetcher restarting on the window map
+        assert_eq!(fifo(0xB1, &|ppu| ppu.wx = 87), 178);
+        // 6 dots for the fetch plus waiting on the bg fetcher, which depends on where in the
+        // tile the sprite starts
+        assert_eq!(fifo(0x93, &|ppu| with_sprites(ppu, &[0])), 183);
+        assert_eq!(fifo(0x93, &|ppu| with_sprites(ppu, &[8])), 183);
+        assert_eq!(fifo(0x93, &|ppu| with_sprites(ppu, &[12])), 179);
+        assert_eq!(fifo(0x93, &|ppu| with_sprites(ppu, &[15])), 178);
+        assert_eq!(fifo(0x93, &|ppu| { ppu.scx = 3; with_sprites(ppu, &[8]) }), 172 + 3 + 8);
+        // the second sprite in the same tile only pays for its own fetch
+        assert_eq!(fifo(0x93, &|ppu| with_sprites(ppu, &[88, 90])), 172 + 11 + 6);
+        // sprites turned off in LCDC don't stall anything
+        assert_eq!(fifo(0x91, &|ppu| with_sprites(ppu, &[8])), 172);
+        // the scanline renderer always takes 172
+        assert_eq!(mode3_dots(RenderMode::Scanline, 0xB3, |ppu| { ppu.scx = 5; ppu.wx = 87; with_sprites(ppu, &[8]) }).0, 172);
+    }
+}
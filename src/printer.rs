@@ -0,0 +1,376 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::png;
+use crate::serial::SerialDevice;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    // the game sends two more zeroes, we answer with 0x81 and then the status
+    KeepAlive,
+    Status,
+}
+
+// Game Boy Printer on the link port, every finished page ends up as a png in out_dir
+#[derive(Debug)]
+pub struct GameBoyPrinter {
+    out_dir: PathBuf,
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    // status polls left that still report the printer as busy
+    busy_polls: u8,
+    // tile data from the data packets since the last print, 2bpp tiles 20 to a row
+    buffer: Vec<u8>,
+    // shades of the current page, strips printed without a margin after them get stuck together
+    page: Vec<u8>,
+    pages_printed: u32,
+}
+
+impl GameBoyPrinter {
+
+    const MAGIC1: u8 = 0x88;
+    const MAGIC2: u8 = 0x33;
+
+    const CMD_INIT: u8 = 0x01;
+    const CMD_PRINT: u8 = 0x02;
+    const CMD_DATA: u8 = 0x04;
+    const CMD_STATUS: u8 = 0x0F;
+
+    const STATUS_CHECKSUM_ERROR: u8 = 0x01;
+    const STATUS_PRINTING: u8 = 0x02;
+    const STATUS_FULL: u8 = 0x04;
+    const STATUS_UNPROCESSED: u8 = 0x08;
+
+    const ALIVE: u8 = 0x81;
+    const WIDTH: usize = 160;
+    const TILES_PER_ROW: usize = Self::WIDTH / 8;
+    const TILE_ROW_BYTES: usize = Self::TILES_PER_ROW * 16;
+    // 9 data packets of 2 tile rows each, 144 lines
+    const BUFFER_SIZE: usize = 9 * 2 * Self::TILE_ROW_BYTES;
+    const BUSY_POLLS: u8 = 4;
+
+    pub fn new<P: Into<PathBuf>>(out_dir: P) -> Self{
+        GameBoyPrinter {
+            out_dir: out_dir.into(),
+            state: PacketState::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            busy_polls: 0,
+            buffer: Vec::new(),
+            page: Vec::new(),
+            pages_printed: 0,
+        }
+    }
+
+    fn receive(&mut self, val: u8) -> u8{
+        match self.state {
+            PacketState::Magic1 => {
+                if val == Self::MAGIC1 {
+                    self.state = PacketState::Magic2;
+                }
+            },
+            PacketState::Magic2 => {
+                self.state = match val {
+                    Self::MAGIC2 => PacketState::Command,
+                    Self::MAGIC1 => PacketState::Magic2,
+                    _ => PacketState::Magic1
+                };
+            },
+            PacketState::Command => {
+                self.command = val;
+                self.checksum = val as u16;
+                self.state = PacketState::Compression;
+            },
+            PacketState::Compression => {
+                self.compressed = val & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(val as u16);
+                self.state = PacketState::LengthLow;
+            },
+            PacketState::LengthLow => {
+                self.length = val as usize;
+                self.checksum = self.checksum.wrapping_add(val as u16);
+                self.state = PacketState::LengthHigh;
+            },
+            PacketState::LengthHigh => {
+                self.length |= (val as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(val as u16);
+                self.data.clear();
+                self.state = if self.length == 0 { PacketState::ChecksumLow } else { PacketState::Data };
+            },
+            PacketState::Data => {
+                self.data.push(val);
+                self.checksum = self.checksum.wrapping_add(val as u16);
+                if self.data.len() == self.length {
+                    self.state = PacketState::ChecksumLow;
+                }
+            },
+            PacketState::ChecksumLow => {
+                self.received_checksum = val as u16;
+                self.state = PacketState::ChecksumHigh;
+            },
+            PacketState::ChecksumHigh => {
+                self.received_checksum |= (val as u16) << 8;
+                self.state = PacketState::KeepAlive;
+            },
+            PacketState::KeepAlive => {
+                self.state = PacketState::Status;
+                return Self::ALIVE;
+            },
+            PacketState::Status => {
+                self.state = PacketState::Magic1;
+                self.run_command();
+                return self.take_status();
+            },
+        }
+        0x00
+    }
+
+    fn run_command(&mut self){
+        if self.checksum != self.received_checksum {
+            self.status |= Self::STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !Self::STATUS_CHECKSUM_ERROR;
+        match self.command {
+            Self::CMD_INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy_polls = 0;
+            },
+            Self::CMD_DATA => {
+                // an empty data packet just marks the end of the data
+                let data = std::mem::take(&mut self.data);
+                if self.compressed {
+                    self.decompress(&data);
+                }
+                else {
+                    self.buffer.extend_from_slice(&data);
+                }
+                self.buffer.truncate(Self::BUFFER_SIZE);
+                if !self.buffer.is_empty() {
+                    self.status |= Self::STATUS_UNPROCESSED;
+                }
+                if self.buffer.len() == Self::BUFFER_SIZE {
+                    self.status |= Self::STATUS_FULL;
+                }
+            },
+            Self::CMD_PRINT => {
+                if self.data.len() >= 4 {
+                    let margins = self.data[1];
+                    let palette = self.data[2];
+                    self.print(palette, margins & 0x0F);
+                }
+                self.status &= !(Self::STATUS_UNPROCESSED | Self::STATUS_FULL);
+                self.busy_polls = Self::BUSY_POLLS;
+            },
+            Self::CMD_STATUS => (),
+            cmd => println!("printer got unknown command {:02X}", cmd),
+        }
+    }
+
+    fn take_status(&mut self) -> u8{
+        let mut status = self.status;
+        if self.busy_polls > 0 {
+            self.busy_polls -= 1;
+            status |= Self::STATUS_PRINTING;
+        }
+        status
+    }
+
+    // a control byte with bit 7 set repeats the next byte (n & 0x7F) + 2 times,
+    // otherwise n + 1 plain bytes follow
+    fn decompress(&mut self, data: &[u8]){
+        let mut i = 0;
+        while i < data.len() {
+            let control = data[i];
+            i += 1;
+            if control & 0x80 != 0 {
+                let Some(&val) = data.get(i) else {
+                    break;
+                };
+                let run = (control & 0x7F) as usize + 2;
+                self.buffer.extend(std::iter::repeat_n(val, run));
+                i += 1;
+            }
+            else {
+                let end = (i + control as usize + 1).min(data.len());
+                self.buffer.extend_from_slice(&data[i..end]);
+                i = end;
+            }
+        }
+    }
+
+    // turns the buffered tiles into shades and adds them to the page
+    fn print(&mut self, palette: u8, margin_after: u8){
+        // a zero palette means the default one
+        let palette = if palette == 0 { 0xE4 } else { palette };
+        let tile_rows = self.buffer.len() / Self::TILE_ROW_BYTES;
+        for tile_row in 0..tile_rows {
+            for y in 0..8 {
+                for x in 0..Self::WIDTH {
+                    let tile = tile_row * Self::TILES_PER_ROW + x / 8;
+                    let lo = self.buffer[tile * 16 + y * 2];
+                    let hi = self.buffer[tile * 16 + y * 2 + 1];
+                    let bit = 7 - (x % 8);
+                    let color = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+                    self.page.push((palette >> (color * 2)) & 0x03);
+                }
+            }
+        }
+        self.buffer.clear();
+        if margin_after > 0 {
+            self.finish_page();
+        }
+    }
+
+    fn finish_page(&mut self){
+        if self.page.is_empty() {
+            return;
+        }
+        self.pages_printed += 1;
+        let path = self.out_dir.join(format!("print_{:04}.png", self.pages_printed));
+        let pixels: Vec<u8> = self.page.iter().map(|shade| 0xFF - shade * 0x55).collect();
+        let height = pixels.len() / Self::WIDTH;
+        let result = fs::create_dir_all(&self.out_dir)
+            .and_then(|_| png::write_grayscale(&path, Self::WIDTH, height, &pixels));
+        match result {
+            Ok(()) => println!("printed {}", path.display()),
+            Err(err) => println!("couldn't write printout {}: {}", path.display(), err),
+        }
+        self.page.clear();
+    }
+}
+
+impl SerialDevice for GameBoyPrinter {
+    fn exchange(&mut self, out: u8) -> u8{
+        self.receive(out)
+    }
+}
+
+// whatever was printed without a trailing margin still gets written out
+impl Drop for GameBoyPrinter {
+    fn drop(&mut self){
+        self.finish_page();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf{
+        let dir = std::env::temp_dir().join(format!("sastaboy_printer_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // sends a whole packet, returns the last two bytes back (alive and status)
+    fn send_packet(printer: &mut GameBoyPrinter, command: u8, compressed: bool, data: &[u8]) -> (u8, u8){
+        let mut packet = vec![0x88, 0x33, command, compressed as u8, data.len() as u8, (data.len() >> 8) as u8];
+        packet.extend_from_slice(data);
+        let checksum = packet[2..].iter().fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+        packet.extend_from_slice(&checksum.to_le_bytes());
+        packet.extend_from_slice(&[0x00, 0x00]);
+        let replies: Vec<u8> = packet.iter().map(|byte| printer.exchange(*byte)).collect();
+        (replies[replies.len() - 2], replies[replies.len() - 1])
+    }
+
+    // two tile rows, every tile column of 5 tiles in one of the 4 colors
+    fn stripes() -> Vec<u8>{
+        let mut data = Vec::new();
+        for tile in 0..40 {
+            let color = (tile % 20) / 5;
+            for _ in 0..8 {
+                data.push(if color & 1 != 0 { 0xFF } else { 0x00 });
+                data.push(if color & 2 != 0 { 0xFF } else { 0x00 });
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn decompress_runs_and_literals(){
+        let mut printer = GameBoyPrinter::new(test_dir("rle"));
+        printer.decompress(&[0x81, 0xAA, 0x02, 0x01, 0x02, 0x03, 0x80, 0x55]);
+        assert_eq!(printer.buffer, [0xAA, 0xAA, 0xAA, 0x01, 0x02, 0x03, 0x55, 0x55]);
+    }
+
+    #[test]
+    fn compressed_data_matches_plain_data(){
+        let mut plain = GameBoyPrinter::new(test_dir("plain"));
+        send_packet(&mut plain, GameBoyPrinter::CMD_DATA, false, &[0x11; 640]);
+        let mut compressed = GameBoyPrinter::new(test_dir("compressed"));
+        // 640 bytes as runs of at most 129
+        let mut rle = Vec::new();
+        let mut left = 640;
+        while left > 0 {
+            let run = left.min(129);
+            rle.extend_from_slice(&[0x80 | (run - 2) as u8, 0x11]);
+            left -= run;
+        }
+        send_packet(&mut compressed, GameBoyPrinter::CMD_DATA, true, &rle);
+        assert_eq!(plain.buffer.len(), 640);
+        assert_eq!(plain.buffer, compressed.buffer);
+    }
+
+    #[test]
+    fn bad_checksum_is_reported(){
+        let mut printer = GameBoyPrinter::new(test_dir("checksum"));
+        let mut replies = Vec::new();
+        for byte in [0x88, 0x33, 0x0F, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00] {
+            replies.push(printer.exchange(byte));
+        }
+        assert_eq!(replies[8], GameBoyPrinter::ALIVE);
+        assert_eq!(replies[9] & GameBoyPrinter::STATUS_CHECKSUM_ERROR, GameBoyPrinter::STATUS_CHECKSUM_ERROR);
+    }
+
+    #[test]
+    fn packets_to_png(){
+        let dir = test_dir("png");
+        let mut printer = GameBoyPrinter::new(&dir);
+        assert_eq!(send_packet(&mut printer, GameBoyPrinter::CMD_INIT, false, &[]), (0x81, 0x00));
+        assert_eq!(send_packet(&mut printer, GameBoyPrinter::CMD_DATA, false, &stripes()), (0x81, 0x08));
+        assert_eq!(send_packet(&mut printer, GameBoyPrinter::CMD_DATA, false, &[]), (0x81, 0x08));
+        // no margin after, the next strip goes on the same page
+        let (_, status) = send_packet(&mut printer, GameBoyPrinter::CMD_PRINT, false, &[0x01, 0x00, 0xE4, 0x40]);
+        assert_eq!(status & GameBoyPrinter::STATUS_PRINTING, GameBoyPrinter::STATUS_PRINTING);
+        assert!(!dir.join("print_0001.png").exists());
+        send_packet(&mut printer, GameBoyPrinter::CMD_DATA, false, &stripes());
+        send_packet(&mut printer, GameBoyPrinter::CMD_PRINT, false, &[0x01, 0x03, 0xE4, 0x40]);
+
+        let png = fs::read(dir.join("print_0001.png")).unwrap();
+        assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), 160);
+        assert_eq!(u32::from_be_bytes(png[20..24].try_into().unwrap()), 32);
+        // IDAT right after IHDR, then the zlib header and the stored block header
+        let raw = &png[33 + 8 + 2 + 5..];
+        let row: Vec<u8> = (0..4).map(|stripe| raw[1 + stripe * 40]).collect();
+        assert_eq!(row, [0xFF, 0xAA, 0x55, 0x00]);
+        // last line of the second strip
+        let last = 31 * 161;
+        assert_eq!(raw[last], 0);
+        assert_eq!(raw[last + 1 + 159], 0x00);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,68 +1,423 @@
 use std::rc::{Rc, Weak};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::apu::APU;
+use crate::cartridge::{Cartridge, CartridgeError};
 use crate::cpu::CPU;
 use crate::interrupt::InterruptHandlerThing;
+use crate::joypad::{Buttons, Joypad};
+use crate::mbc;
 use crate::memory::Mem;
+use crate::model::Model;
+use crate::ppu::{PPU, RenderMode, SCREEN_WIDTH, SCREEN_HEIGHT};
+use crate::serial::{Serial, SerialDevice};
 use crate::timer::Timer;
+use crate::wav::WavWriter;
 
 #[derive(Debug)]
 pub struct SastaBoy {
     pub cpu: Rc<RefCell<CPU>>,
     interrupt_handler: Rc<RefCell<InterruptHandlerThing>>,
     mem: Rc<RefCell<Mem>>,
-    timer: Rc<RefCell<Timer>>
+    timer: Rc<RefCell<Timer>>,
+    ppu: Rc<RefCell<PPU>>,
+    joypad: Rc<RefCell<Joypad>>,
+    apu: Rc<RefCell<APU>>,
+    model: Model,
+    serial: Rc<RefCell<Serial>>,
+    // only set for carts with a battery
+    save_path: RefCell<Option<PathBuf>>,
+    last_saved: RefCell<Vec<u8>>,
+    // set by record_audio, everything the apu mixes goes in here
+    wav: RefCell<Option<WavWriter>>,
+    cycles_since_save: Cell<u32>,
+    cycles_since_flush: Cell<u32>,
+    // what the terminal was last told about the rumble motor, and how long it's been off since
+    rumbling: Cell<bool>,
+    cycles_without_rumble: Cell<u32>
 }
 
 impl SastaBoy {
+    #[allow(dead_code)]
     pub fn new() -> Self{
+        Self::with_model(Model::DMG)
+    }
+
+    // starts out in the state the model's boot rom leaves behind
+    pub fn with_model(model: Model) -> Self{
         let interrupt_handler = Rc::new(RefCell::new(InterruptHandlerThing::new()));
         let mem = Rc::new(RefCell::new(Mem::new(Weak::new(),Rc::downgrade(&interrupt_handler))));
         let timer = Rc::new(RefCell::new(Timer::new(Rc::downgrade(&interrupt_handler))));
+        let ppu = Rc::new(RefCell::new(PPU::new(Rc::downgrade(&interrupt_handler))));
         mem.borrow_mut().timer = Rc::downgrade(&timer);
+        let joypad = Rc::new(RefCell::new(Joypad::new(Rc::downgrade(&interrupt_handler))));
+        mem.borrow_mut().ppu = Rc::downgrade(&ppu);
+        let apu = Rc::new(RefCell::new(APU::new()));
+        mem.borrow_mut().joypad = Rc::downgrade(&joypad);
+        mem.borrow_mut().apu = Rc::downgrade(&apu);
+        let serial = Rc::new(RefCell::new(Serial::new(Rc::downgrade(&interrupt_handler), model.is_cgb())));
+        mem.borrow_mut().serial = Rc::downgrade(&serial);
         let cpu = Rc::new(RefCell::new(CPU::new(Rc::downgrade(&mem), Rc::downgrade(&interrupt_handler))));
 
-        SastaBoy { 
-            cpu: cpu,
-            interrupt_handler: interrupt_handler,
-            mem: mem,
-            timer: timer    
+        let sasta_boy = SastaBoy {
+            cpu,
+            interrupt_handler,
+            mem,
+            timer,
+            ppu,
+            joypad,
+            apu,
+            model,
+            serial,
+            save_path: RefCell::new(None),
+            last_saved: RefCell::new(Vec::new()),
+            wav: RefCell::new(None),
+            cycles_since_save: Cell::new(0),
+            cycles_since_flush: Cell::new(0),
+            rumbling: Cell::new(false),
+            cycles_without_rumble: Cell::new(0)
+        };
+        sasta_boy.apply_post_boot_state();
+        sasta_boy
+    }
+
+    fn apply_post_boot_state(&self){
+        // no cart yet, assume a non zero header checksum like nearly every rom has
+        self.cpu.borrow_mut().set_post_boot_state(self.model, 0x01);
+        self.timer.borrow_mut().set_div_counter(self.model.div_counter());
+        self.mem.borrow_mut().set_dma_register(self.model.dma_register());
+        for (addr, val) in self.model.io_registers() {
+            self.mem.borrow_mut().write(addr, val);
         }
     }
 
-    pub fn load_rom(&self,path: &str) {
-        let data = fs::read(path).expect("Failed to read ROM file");
-        for i in 0..data.len(){
-            self.mem.borrow_mut().write(i, data[i]);
+    pub fn model(&self) -> Model{
+        self.model
+    }
+
+    // write battery backed ram out once every few emulated seconds if it changed
+    const SAVE_INTERVAL_MCYCLES: u32 = 5 * CPU::MCYCLES_PER_SECOND;
+    // roughly a frame, the apu buffer is drained into the wav file this often
+    const AUDIO_FLUSH_MCYCLES: u32 = 17556;
+    // games pulse the motor to make weaker rumble, so it has to stay off this long to count as stopped
+    const RUMBLE_OFF_MCYCLES: u32 = CPU::MCYCLES_PER_SECOND / 4;
+
+    pub fn load_rom(&self,path: &str) -> Result<(), CartridgeError> {
+        let data = fs::read(path)?;
+        let cart = Cartridge::from_bytes(data)?;
+        println!("title: {} type: {:02X} ({:?}) version: {}", cart.title, cart.cartridge_type, cart.mapper, cart.version);
+        println!("rom size: {} ram size: {}", cart.rom_size, cart.ram_size);
+        if !cart.header_checksum_valid(){
+            println!("header checksum doesn't match!");
+        }
+        let has_battery = cart.has_battery;
+        let header_checksum = cart.header_checksum;
+        let mut mbc = mbc::new_mbc(cart)?;
+        // the dmg boot rom's flags depend on the header checksum
+        if !self.mem.borrow().boot_rom_mapped() {
+            self.cpu.borrow_mut().set_post_boot_state(self.model, header_checksum);
         }
-        println!("rom size: {}",data.len());
+        if has_battery {
+            let save_path = Path::new(path).with_extension("sav");
+            if let Ok(save) = fs::read(&save_path) {
+                mbc.load_save_data(&save);
+                println!("loaded save from {}", save_path.display());
+            }
+            *self.last_saved.borrow_mut() = mbc.save_contents();
+            *self.save_path.borrow_mut() = Some(save_path);
+        }
+        self.mem.borrow_mut().cartridge = Some(mbc);
         println!("Rom Loaded!");
+        Ok(())
     }
 
-    pub fn run(&self){
-        let mut output_buffer = String::from("");
-        while self.cpu.borrow().pc < 0xFFFF {
-            let mut mcycles = self.cpu.borrow_mut().execute();
-            self.timer.borrow_mut().tick(mcycles);
-            mcycles = 0;
-            let interrupt_isr = self.interrupt_handler.borrow_mut().check_interrupt();
-            if interrupt_isr != 0 {
-                self.cpu.borrow_mut().handle_interrupt(interrupt_isr);
-                mcycles += 5;
+    // runs the dmg boot rom (logo scroll, header checks) instead of starting at 0x100
+    // with the post boot state, call it before run
+    pub fn load_boot_rom(&self, path: &str) -> io::Result<()>{
+        let data = fs::read(path)?;
+        if data.len() != Mem::BOOT_ROM_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("boot ROM should be {} bytes, got {}", Mem::BOOT_ROM_SIZE, data.len())));
+        }
+        self.mem.borrow_mut().set_boot_rom(data);
+        self.cpu.borrow_mut().start_boot_rom();
+        // undo the post boot state, the boot rom starts with the lcd and apu off
+        self.timer.borrow_mut().set_div_counter(0);
+        for (addr, val) in [(APU::NR52_ADDR, 0x00), (PPU::LCDC_ADDR, 0x00), (PPU::STAT_ADDR, 0x00),
+                (PPU::BGP_ADDR, 0x00), (Timer::TAC_ADDR, 0x00), (InterruptHandlerThing::IF_ADDR, 0x00)] {
+            self.mem.borrow_mut().write(addr, val);
+        }
+        println!("Boot ROM Loaded!");
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn framebuffer(&self) -> [u8; SCREEN_WIDTH * SCREEN_HEIGHT]{
+        self.ppu.borrow().framebuffer
+    }
+
+    pub fn set_render_mode(&self, mode: RenderMode){
+        self.ppu.borrow_mut().render_mode = mode;
+    }
+
+    // replaces the whole button state, for frontends and input scripts
+    #[allow(dead_code)]
+    pub fn set_buttons(&self, buttons: Buttons){
+        self.joypad.borrow_mut().set_buttons(buttons);
+    }
+
+    // for frontends that can shake something when an MBC5 rumble cart turns its motor on
+    pub fn rumble_active(&self) -> bool{
+        self.mem.borrow().cartridge.as_ref().is_some_and(|cart| cart.rumble())
+    }
+
+    // plugs something into the link port, replacing whatever was there
+    pub fn set_serial_device(&self, device: Box<dyn SerialDevice>){
+        self.serial.borrow_mut().set_device(Some(device));
+    }
+
+    #[allow(dead_code)]
+    pub fn unplug_serial_device(&self){
+        self.serial.borrow_mut().set_device(None);
+    }
+
+    pub fn set_sample_rate(&self, rate: u32){
+        self.apu.borrow_mut().set_sample_rate(rate);
+    }
+
+    // samples mixed since the last call, interleaved left/right, for frontends doing their own playback
+    #[allow(dead_code)]
+    pub fn take_samples(&self) -> Vec<f32>{
+        self.apu.borrow_mut().take_samples()
+    }
+
+    // dumps the audio of the rest of the run into a wav file, set the sample rate before this
+    pub fn record_audio(&self, path: &str) -> io::Result<()>{
+        let sample_rate = self.apu.borrow().sample_rate();
+        let writer = WavWriter::create(Path::new(path), sample_rate)?;
+        self.apu.borrow_mut().take_samples();
+        *self.wav.borrow_mut() = Some(writer);
+        Ok(())
+    }
+
+    fn flush_audio(&self){
+        let mut wav = self.wav.borrow_mut();
+        let Some(writer) = wav.as_mut() else {
+            return;
+        };
+        let samples = self.apu.borrow_mut().take_samples();
+        if let Err(err) = writer.write_samples(&samples) {
+            println!("couldn't write audio: {}", err);
+            *wav = None;
+        }
+    }
+
+    fn finish_audio(&self){
+        self.flush_audio();
+        if let Some(writer) = self.wav.borrow_mut().take() {
+            if let Err(err) = writer.finish() {
+                println!("couldn't finish audio file: {}", err);
             }
-            self.timer.borrow_mut().tick(mcycles);
-
-            // printing serial port for blargg's test output
-            if self.mem.borrow().read(0xFF02) == 0x81{
-                let c = self.mem.borrow().read(0xFF01);
-                print!("{}", c as char);
-                output_buffer.push(c as char);
-                if output_buffer.contains("Passed") || output_buffer.contains("Failed"){
-                    break;
-                }
-                self.mem.borrow_mut().write(0xFF02, 0x0);
+        }
+    }
+
+    // writes the .sav file next to the rom, skipped when nothing changed since the last one
+    pub fn save(&self) -> io::Result<()>{
+        let save_path = self.save_path.borrow();
+        let Some(path) = save_path.as_ref() else {
+            return Ok(());
+        };
+        let mem = self.mem.borrow();
+        let Some(cart) = mem.cartridge.as_ref() else {
+            return Ok(());
+        };
+        let contents = cart.save_contents();
+        if *self.last_saved.borrow() == contents {
+            return Ok(());
+        }
+        fs::write(path, cart.save_data())?;
+        *self.last_saved.borrow_mut() = contents;
+        Ok(())
+    }
+
+    fn save_or_complain(&self){
+        if let Err(err) = self.save() {
+            println!("couldn't write save file: {}", err);
+        }
+    }
+
+    // one instruction plus the interrupt dispatch if there is one, returns the m-cycles that took
+    pub fn step(&self) -> u32{
+        let mut total: u32 = 0;
+        let mut mcycles = self.cpu.borrow_mut().execute();
+        self.tick_components(mcycles);
+        total += mcycles as u32;
+        mcycles = 0;
+        let interrupt_isr = self.interrupt_handler.borrow_mut().check_interrupt();
+        if interrupt_isr != 0 {
+            self.cpu.borrow_mut().handle_interrupt(interrupt_isr);
+            mcycles += 5;
+        }
+        self.tick_components(mcycles);
+        total += mcycles as u32;
+
+        self.cycles_since_save.set(self.cycles_since_save.get() + total);
+        if self.cycles_since_save.get() >= Self::SAVE_INTERVAL_MCYCLES {
+            self.cycles_since_save.set(0);
+            self.save_or_complain();
+        }
+        self.cycles_since_flush.set(self.cycles_since_flush.get() + total);
+        if self.cycles_since_flush.get() >= Self::AUDIO_FLUSH_MCYCLES {
+            self.cycles_since_flush.set(0);
+            self.flush_audio();
+        }
+        self.report_rumble(total);
+        total
+    }
+
+    // the terminal is all the frontend there is, so the motor starting and stopping gets a line there
+    fn report_rumble(&self, mcycles: u32){
+        if self.rumble_active() {
+            self.cycles_without_rumble.set(0);
+            if !self.rumbling.replace(true) {
+                println!("Rumble on");
             }
-            
         }
+        else if self.rumbling.get() {
+            self.cycles_without_rumble.set(self.cycles_without_rumble.get() + mcycles);
+            if self.cycles_without_rumble.get() >= Self::RUMBLE_OFF_MCYCLES {
+                self.rumbling.set(false);
+                println!("Rumble off");
+            }
+        }
+    }
+
+    fn tick_components(&self, mcycles: u8){
+        self.timer.borrow_mut().tick(mcycles);
+        self.ppu.borrow_mut().tick(mcycles);
+        self.apu.borrow_mut().tick(mcycles);
+        self.serial.borrow_mut().tick(mcycles);
+        self.mem.borrow_mut().tick(mcycles);
+    }
+
+    // the cpu ran off the end of memory or whatever is on the link port says we're done
+    pub fn stopped(&self) -> bool{
+        self.cpu.borrow().pc == 0xFFFF || self.serial.borrow().device_finished()
+    }
+
+    // flushes the save file and the audio recording, call it when done stepping by hand
+    pub fn finish(&self){
+        self.save_or_complain();
+        self.finish_audio();
+    }
+
+    pub fn run(&self){
+        while !self.stopped() {
+            self.step();
+        }
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn post_boot_io_depends_on_the_model(){
+        for (model, nr52, div, sc, dma) in [(Model::DMG, 0xF1, 0xAB, 0x7E, 0xFF), (Model::SGB, 0xF0, 0xAB, 0x7E, 0xFF),
+                (Model::CGB, 0xF1, 0x1E, 0x7F, 0x00), (Model::AGB, 0xF1, 0x1E, 0x7F, 0x00)] {
+            let sasta_boy = SastaBoy::with_model(model);
+            let mem = sasta_boy.mem.borrow();
+            assert_eq!(mem.read(APU::NR52_ADDR), nr52, "{:?}", model);
+            assert_eq!(mem.read(Timer::DIV_ADDR), div, "{:?}", model);
+            assert_eq!(mem.read(Serial::SC_ADDR), sc, "{:?}", model);
+            assert_eq!(mem.read(Mem::DMA_ADDR), dma, "{:?}", model);
+            assert_eq!(mem.read(PPU::LCDC_ADDR), 0x91, "{:?}", model);
+            assert_eq!(mem.read(PPU::BGP_ADDR), 0xFC, "{:?}", model);
+            assert_eq!(sasta_boy.cpu.borrow().pc, 0x0100);
+        }
+        assert_eq!(Model::from_name("SGB2"), Some(Model::SGB2));
+        assert_eq!(Model::from_name("gbc"), None);
+    }
+
+    fn oam(sasta_boy: &SastaBoy) -> Vec<u8>{
+        let ppu = sasta_boy.ppu.borrow();
+        (PPU::OAM_START..=PPU::OAM_END).map(|addr| ppu.read_oam(addr)).collect()
+    }
+
+    #[test]
+    fn oam_dma_copies_160_bytes_and_blocks_the_bus(){
+        let sasta_boy = SastaBoy::new();
+        for i in 0..0xA0 {
+            sasta_boy.mem.borrow_mut().write(0xC000 + i, i as u8);
+            sasta_boy.mem.borrow_mut().write(0xC100 + i, 0xFF - i as u8);
+        }
+        sasta_boy.mem.borrow_mut().write(0xFF80, 0x42);
+
+        sasta_boy.mem.borrow_mut().write(Mem::DMA_ADDR, 0xC0);
+        assert_eq!(sasta_boy.mem.borrow().read(Mem::DMA_ADDR), 0xC0);
+        // a cycle before it starts
+        sasta_boy.tick_components(1);
+        assert_eq!(sasta_boy.mem.borrow().read(0xC001), 0x01);
+        sasta_boy.tick_components(1);
+        for _ in 0..0xA0 {
+            // only hram and the io registers are reachable while it runs
+            assert_eq!(sasta_boy.mem.borrow().read(0xC001), 0xFF);
+            assert_eq!(sasta_boy.mem.borrow().read(0xFF80), 0x42);
+            assert_eq!(sasta_boy.mem.borrow().read(Mem::DMA_ADDR), 0xC0);
+            sasta_boy.tick_components(1);
+        }
+        assert_eq!(sasta_boy.mem.borrow().read(0xC001), 0x01);
+        assert_eq!(oam(&sasta_boy), (0..0xA0).map(|i| i as u8).collect::<Vec<u8>>());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn oam_dma_restart_starts_over_from_the_new_source(){
+        let sasta_boy = SastaBoy::new();
+        for i in 0..0xA0 {
+            sasta_boy.mem.borrow_mut().write(0xC000 + i, i as u8);
+            sasta_boy.mem.borrow_mut().write(0xC100 + i, 0xFF - i as u8);
+        }
+        sasta_boy.mem.borrow_mut().write(Mem::DMA_ADDR, 0xC0);
+        sasta_boy.tick_components(2 + 0x10);
+        // FF46 stays writable, the old transfer keeps going until the new one takes over
+        sasta_boy.mem.borrow_mut().write(Mem::DMA_ADDR, 0xC1);
+        assert_eq!(oam(&sasta_boy)[0x10], 0x00);
+        sasta_boy.tick_components(1);
+        assert_eq!(oam(&sasta_boy)[0x10], 0x10);
+        for _ in 0..=0xA0 {
+            assert_eq!(sasta_boy.mem.borrow().read(0xC001), 0xFF);
+            sasta_boy.tick_components(1);
+        }
+        assert_eq!(sasta_boy.mem.borrow().read(0xC001), 0x01);
+        assert_eq!(oam(&sasta_boy), (0..0xA0).map(|i| 0xFF - i as u8).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn rumble_is_reported_until_the_motor_stays_off(){
+        let sasta_boy = SastaBoy::new();
+        sasta_boy.mem.borrow_mut().cartridge = Some(Box::new(mbc::Mbc5::new(vec![0x00; 0x8000], 0, true)));
+        sasta_boy.mem.borrow_mut().write(0x4000, 0x08);
+        assert!(sasta_boy.rumble_active());
+        sasta_boy.step();
+        assert!(sasta_boy.rumbling.get());
+
+        // a short gap is just the game pulsing the motor
+        sasta_boy.mem.borrow_mut().write(0x4000, 0x00);
+        assert!(!sasta_boy.rumble_active());
+        for _ in 0..1000 {
+            sasta_boy.step();
+        }
+        assert!(sasta_boy.rumbling.get());
+        sasta_boy.mem.borrow_mut().write(0x4000, 0x08);
+        sasta_boy.step();
+        sasta_boy.mem.borrow_mut().write(0x4000, 0x00);
+        for _ in 0..SastaBoy::RUMBLE_OFF_MCYCLES {
+            sasta_boy.step();
+        }
+        assert!(!sasta_boy.rumbling.get());
+    }
+}
@@ -0,0 +1,145 @@
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::rc::Weak;
+
+use crate::interrupt::InterruptHandlerThing;
+
+// whatever is plugged into the link port
+pub trait SerialDevice: Debug {
+    // we drive the clock and just shifted `out` over, returns the byte that came back
+    fn exchange(&mut self, out: u8) -> u8;
+
+    // called every tick, `waiting` has SB while we wait on the other side's clock.
+    // returns the byte the other side clocked in, if it did
+    fn tick(&mut self, _mcycles: u8, _waiting: Option<u8>) -> Option<u8>{
+        None
+    }
+
+    // lets a device end the run, e.g. once a test rom printed its result
+    fn finished(&self) -> bool{
+        false
+    }
+}
+
+#[derive(Debug)]
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    // cgb and agb have the clock speed select in SC bit 1
+    speed_bit: bool,
+    // t-cycles into the current transfer when we're the one clocking
+    transfer_tcycles: u32,
+    device: Option<Box<dyn SerialDevice>>,
+    interrupt_handler: Weak<RefCell<InterruptHandlerThing>>,
+}
+
+impl Serial {
+
+    pub const SB_ADDR: usize = 0xFF01;
+    pub const SC_ADDR: usize = 0xFF02;
+
+    // 8192 Hz internal clock, 512 t-cycles a bit
+    const TRANSFER_TCYCLES: u32 = 8 * 512;
+
+    pub fn new(intrrpt: Weak<RefCell<InterruptHandlerThing>>, speed_bit: bool) -> Self{
+        Serial{
+            sb: 0x00,
+            sc: 0x00,
+            speed_bit,
+            transfer_tcycles: 0,
+            device: None,
+            interrupt_handler: intrrpt,
+        }
+    }
+
+    pub fn set_device(&mut self, device: Option<Box<dyn SerialDevice>>){
+        self.device = device;
+    }
+
+    pub fn device_finished(&self) -> bool{
+        self.device.as_ref().is_some_and(|device| device.finished())
+    }
+
+    pub fn read_sb(&self) -> u8{
+        self.sb
+    }
+
+    pub fn write_sb(&mut self, val: u8){
+        self.sb = val;
+    }
+
+    // unused bits read as 1. the speed bit reads back but transfers always run at 8192 Hz
+    pub fn read_sc(&self) -> u8{
+        self.sc | !self.sc_mask()
+    }
+
+    pub fn write_sc(&mut self, val: u8){
+        self.sc = val & self.sc_mask();
+        self.transfer_tcycles = 0;
+    }
+
+    fn sc_mask(&self) -> u8{
+        if self.speed_bit { 0x83 } else { 0x81 }
+    }
+
+    fn transferring(&self) -> bool{
+        self.sc & 0x80 != 0
+    }
+
+    fn internal_clock(&self) -> bool{
+        self.sc & 0x01 != 0
+    }
+
+    fn complete_transfer(&mut self, incoming: u8){
+        self.sb = incoming;
+        self.sc &= !0x80;
+        self.transfer_tcycles = 0;
+        let interrupt_handl = self.interrupt_handler.upgrade().expect("Interrupt handler reference dropped!");
+        interrupt_handl.borrow_mut().req_serial_link();
+    }
+
+    pub fn tick(&mut self, mcycles: u8){
+        if mcycles == 0 {
+            return;
+        }
+        let waiting = if self.transferring() && !self.internal_clock() { Some(self.sb) } else { None };
+        let clocked_in = self.device.as_mut().and_then(|device| device.tick(mcycles, waiting));
+        if let (Some(_), Some(incoming)) = (waiting, clocked_in) {
+            self.complete_transfer(incoming);
+            return;
+        }
+
+        if self.transferring() && self.internal_clock() {
+            self.transfer_tcycles += mcycles as u32 * 4;
+            if self.transfer_tcycles >= Self::TRANSFER_TCYCLES {
+                // nothing plugged in means the line just floats high
+                let incoming = self.device.as_mut().map_or(0xFF, |device| device.exchange(self.sb));
+                self.complete_transfer(incoming);
+            }
+        }
+    }
+}
+
+// prints what blargg's test roms send over serial and stops the run once they're done
+#[derive(Debug, Default)]
+pub struct BlarggCapture {
+    output: String,
+}
+
+impl BlarggCapture {
+    pub fn new() -> Self{
+        BlarggCapture::default()
+    }
+}
+
+impl SerialDevice for BlarggCapture {
+    fn exchange(&mut self, out: u8) -> u8{
+        print!("{}", out as char);
+        self.output.push(out as char);
+        0xFF
+    }
+
+    fn finished(&self) -> bool{
+        self.output.contains("Passed") || self.output.contains("Failed")
+    }
+}
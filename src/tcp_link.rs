@@ -0,0 +1,204 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::serial::SerialDevice;
+
+// every frame on the wire is a kind byte and a value byte
+#[derive(Debug, Clone, Copy)]
+enum Frame {
+    // the sender finished another quantum of emulated time
+    Sync,
+    // the sender clocked out a byte and waits for the reply
+    Xfer(u8),
+    // what was in SB on the other end when an Xfer came in
+    Reply(u8),
+}
+
+impl Frame {
+    const SYNC: u8 = 0x01;
+    const XFER: u8 = 0x02;
+    const REPLY: u8 = 0x03;
+
+    fn encode(self) -> [u8; 2]{
+        match self {
+            Frame::Sync => [Self::SYNC, 0x00],
+            Frame::Xfer(val) => [Self::XFER, val],
+            Frame::Reply(val) => [Self::REPLY, val],
+        }
+    }
+
+    fn decode(bytes: [u8; 2]) -> Option<Self>{
+        match bytes[0] {
+            Self::SYNC => Some(Frame::Sync),
+            Self::XFER => Some(Frame::Xfer(bytes[1])),
+            Self::REPLY => Some(Frame::Reply(bytes[1])),
+            _ => None
+        }
+    }
+}
+
+// link cable to another SastaBoy process. both sides send a Sync every quantum and wait
+// for the other one's before going on, so neither gets more than a quantum ahead
+#[derive(Debug)]
+pub struct TcpLink {
+    stream: TcpStream,
+    // filled by the reader thread, the sender side gets dropped when the connection goes away
+    frames: Receiver<Frame>,
+    cycles: u32,
+    syncs_sent: u64,
+    peer_syncs: u64,
+    // the other side clocked a byte at us and is blocked until we reply
+    pending_xfer: Option<u8>,
+    disconnected: bool,
+}
+
+impl TcpLink {
+
+    // one byte transfer worth of m-cycles
+    const SYNC_QUANTUM_MCYCLES: u32 = 1024;
+
+    // blocks until the other side connects
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<Self>{
+        let listener = TcpListener::bind(addr)?;
+        println!("waiting for link partner on {}", listener.local_addr()?);
+        let (stream, peer) = listener.accept()?;
+        println!("link partner connected from {}", peer);
+        Self::from_stream(stream)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self>{
+        let stream = TcpStream::connect(addr)?;
+        println!("connected to link partner at {}", stream.peer_addr()?);
+        Self::from_stream(stream)
+    }
+
+    fn from_stream(stream: TcpStream) -> io::Result<Self>{
+        stream.set_nodelay(true)?;
+        let mut reader = stream.try_clone()?;
+        let (sender, frames) = mpsc::channel();
+        thread::spawn(move || {
+            let mut bytes = [0u8; 2];
+            while reader.read_exact(&mut bytes).is_ok() {
+                let Some(frame) = Frame::decode(bytes) else {
+                    println!("link partner sent garbage, dropping the connection");
+                    break;
+                };
+                if sender.send(frame).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(TcpLink {
+            stream,
+            frames,
+            cycles: 0,
+            syncs_sent: 0,
+            peer_syncs: 0,
+            pending_xfer: None,
+            disconnected: false,
+        })
+    }
+
+    fn disconnect(&mut self){
+        if !self.disconnected {
+            println!("link partner disconnected");
+            self.disconnected = true;
+            self.pending_xfer = None;
+        }
+    }
+
+    fn send(&mut self, frame: Frame){
+        if self.disconnected {
+            return;
+        }
+        if self.stream.write_all(&frame.encode()).is_err() {
+            self.disconnect();
+        }
+    }
+
+    fn recv(&mut self, block: bool) -> Option<Frame>{
+        if self.disconnected {
+            return None;
+        }
+        let frame = if block {
+            self.frames.recv().ok()
+        }
+        else {
+            match self.frames.try_recv() {
+                Ok(frame) => Some(frame),
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => None,
+            }
+        };
+        if frame.is_none() {
+            self.disconnect();
+        }
+        frame
+    }
+
+    // anything but a Reply, those only matter inside exchange
+    fn handle(&mut self, frame: Frame){
+        match frame {
+            Frame::Sync => self.peer_syncs += 1,
+            Frame::Xfer(val) => self.pending_xfer = Some(val),
+            Frame::Reply(_) => (),
+        }
+    }
+
+    // replies to a pending Xfer, we only take the byte if we were waiting on the external clock
+    fn answer_xfer(&mut self, waiting: Option<u8>) -> Option<u8>{
+        let incoming = self.pending_xfer.take()?;
+        self.send(Frame::Reply(waiting.unwrap_or(0xFF)));
+        waiting.map(|_| incoming)
+    }
+}
+
+impl SerialDevice for TcpLink {
+    fn exchange(&mut self, out: u8) -> u8{
+        self.send(Frame::Xfer(out));
+        while let Some(frame) = self.recv(true) {
+            match frame {
+                Frame::Reply(val) => return val,
+                // both sides clocking at once, neither is listening so both read 0xFF
+                Frame::Xfer(_) => self.send(Frame::Reply(0xFF)),
+                frame => self.handle(frame),
+            }
+        }
+        0xFF
+    }
+
+    fn tick(&mut self, mcycles: u8, waiting: Option<u8>) -> Option<u8>{
+        while let Some(frame) = self.recv(false) {
+            self.handle(frame);
+        }
+        let mut clocked_in = None;
+        if waiting.is_some() {
+            clocked_in = self.answer_xfer(waiting);
+        }
+
+        self.cycles += mcycles as u32;
+        if self.cycles < Self::SYNC_QUANTUM_MCYCLES {
+            return clocked_in;
+        }
+        self.cycles -= Self::SYNC_QUANTUM_MCYCLES;
+        // the other side is blocked on its Xfer, it can't sync until it gets an answer
+        if clocked_in.is_none() {
+            clocked_in = self.answer_xfer(waiting);
+        }
+        self.send(Frame::Sync);
+        self.syncs_sent += 1;
+        while self.peer_syncs < self.syncs_sent {
+            let Some(frame) = self.recv(true) else {
+                break;
+            };
+            self.handle(frame);
+            if self.pending_xfer.is_some() {
+                let waiting = if clocked_in.is_none() { waiting } else { None };
+                clocked_in = clocked_in.or(self.answer_xfer(waiting));
+            }
+        }
+        clocked_in
+    }
+}
@@ -15,6 +15,7 @@ pub struct Timer{
 }
 
 // did not implement obscure timer behaviour and integrate it with the cpu yet
+#[allow(clippy::needless_return)]
 impl Timer {
 
     pub const DIV_ADDR: usize = 0xFF04;
@@ -34,6 +35,11 @@ impl Timer {
         }
     }
 
+    // for starting with a model's post boot state
+    pub fn set_div_counter(&mut self, val: u16){
+        self.div_ctr = val;
+    }
+
     pub fn get_div(&self) -> u8{
         ((self.div_ctr>>8)&0xFF) as u8
     }
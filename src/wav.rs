@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// 16 bit stereo pcm, the sizes in the header get patched in by finish()
+#[derive(Debug)]
+pub struct WavWriter {
+    file: BufWriter<File>,
+    data_bytes: u32,
+}
+
+impl WavWriter {
+    const CHANNELS: u16 = 2;
+    const BITS_PER_SAMPLE: u16 = 16;
+    const HEADER_SIZE: u32 = 44;
+
+    pub fn create(path: &Path, sample_rate: u32) -> io::Result<Self>{
+        let mut file = BufWriter::new(File::create(path)?);
+        let block_align = Self::CHANNELS * Self::BITS_PER_SAMPLE / 8;
+        file.write_all(b"RIFF")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?; // pcm
+        file.write_all(&Self::CHANNELS.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&Self::BITS_PER_SAMPLE.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter {
+            file,
+            data_bytes: 0,
+        })
+    }
+
+    // interleaved left/right samples in -1.0..1.0
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()>{
+        for sample in samples {
+            let val = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.file.write_all(&val.to_le_bytes())?;
+        }
+        self.data_bytes += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()>{
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(Self::HEADER_SIZE - 8 + self.data_bytes).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_bytes.to_le_bytes())?;
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn header_sizes_get_patched_in(){
+        let path = std::env::temp_dir().join(format!("sastaboy_wav_{}.wav", std::process::id()));
+        let mut wav = WavWriter::create(&path, 22050).unwrap();
+        wav.write_samples(&[0.0, 1.0, -1.0, 2.0]).unwrap();
+        wav.finish().unwrap();
+        let data = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let word = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        let half = |i: usize| u16::from_le_bytes(data[i..i + 2].try_into().unwrap());
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(word(4), 36 + 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!((word(16), half(20), half(22)), (16, 1, 2));
+        assert_eq!((word(24), word(28), half(32), half(34)), (22050, 22050 * 4, 4, 16));
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(word(40), 8);
+        // clamped to the i16 range
+        assert_eq!(half(44 + 6), i16::MAX as u16);
+        assert_eq!(half(44 + 4), -i16::MAX as u16);
+    }
+}